@@ -0,0 +1,61 @@
+//! WebAssembly bindings for checking merkle proofs on the client side, without trusting a node.
+//!
+//! Proofs are passed as JSON arrays of `0x` prefixed hex hashes, with `null` on levels where the
+//! node doesn't have a sibling, which is the JSON form of proofs created by `MerkleTreeClient`.
+
+extern crate merkle_proof;
+extern crate rustc_hex;
+extern crate serde_json;
+extern crate wasm_bindgen;
+
+use merkle_proof::{Blake2Hasher, Hasher};
+use rustc_hex::{FromHex, ToHex};
+use wasm_bindgen::prelude::*;
+
+type Hash = [u8; 32];
+
+// Parse `0x` prefixed hex string of a hash
+fn parse_hash(hex: &str) -> Result<Hash, JsValue> {
+    let bytes: Vec<u8> = hex.trim_start_matches("0x").from_hex()
+        .map_err(|_e| JsValue::from_str("Invalid hex hash!"))?;
+    if bytes.len() != 32 {
+        return Err(JsValue::from_str("Hash has to be 32 bytes long!"));
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&bytes);
+    Ok(hash)
+}
+
+// Parse proof from JSON array of hex hashes and nulls
+fn parse_proof(proof: &str) -> Result<Vec<Option<Hash>>, JsValue> {
+    let siblings: Vec<Option<String>> = serde_json::from_str(proof)
+        .map_err(|_e| JsValue::from_str("Invalid proof json!"))?;
+    siblings.iter().map(|sibling| match sibling {
+        Some(hex) => parse_hash(hex).map(Some),
+        None => Ok(None),
+    }).collect()
+}
+
+// Parse leaf index from a decimal string, as JS numbers can't hold every `u128` index
+fn parse_index(index: &str) -> Result<u128, JsValue> {
+    index.parse().map_err(|_e| JsValue::from_str("Invalid decimal node index!"))
+}
+
+// Check that `leaf` was inserted at `node_index`, a decimal string, into the tree state with root of `root`
+// Same as `verify_proof` of the module, except the number of leaves is not known in the browser,
+// so the proof is only bounded by the biggest tree it could belong to
+#[wasm_bindgen(js_name = verifyProof)]
+pub fn verify_proof(proof: &str, leaf: &[u8], node_index: &str, root: &str) -> Result<bool, JsValue> {
+    let proof = parse_proof(proof)?;
+    let node_index = parse_index(node_index)?;
+    let root_hash = parse_hash(root)?;
+    let leaf_hash = Blake2Hasher::hash_leaf(leaf);
+    let n_nodes = merkle_proof::max_n_nodes(proof.len());
+    Ok(merkle_proof::verify_proof::<Blake2Hasher>(&proof, leaf_hash, node_index, n_nodes, root_hash).is_ok())
+}
+
+// Hash of the leaf made from `leaf` bytes, as emitted in the `Insert` event
+#[wasm_bindgen(js_name = hashLeaf)]
+pub fn hash_leaf(leaf: &[u8]) -> String {
+    format!("0x{}", Blake2Hasher::hash_leaf(leaf).to_hex())
+}
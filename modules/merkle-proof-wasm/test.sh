@@ -0,0 +1,14 @@
+#!/usr/bin/env bash
+
+set -e
+
+PROJECT_ROOT="$( cd "$( dirname "${BASH_SOURCE[0]}" )" >/dev/null && pwd )"
+
+cd "$PROJECT_ROOT"
+
+# Install wasm-pack. It builds the package together with its JS bindings.
+command -v wasm-pack || \
+	cargo install wasm-pack
+
+wasm-pack build --target nodejs
+node tests/verify.js
@@ -0,0 +1,32 @@
+// Checks the wasm verifier against the same fixtures the module's `verify_proof` is tested with.
+// Run with `./test.sh`, which builds the package into `pkg` first.
+
+const assert = require('assert');
+const fixtures = require('./fixtures.json');
+const merkle = require('../pkg/merkle_proof_wasm.js');
+
+for (const test of fixtures.cases) {
+	const valid = merkle.verifyProof(JSON.stringify(test.proof), Buffer.from(test.leaf), String(test.node_index), test.root);
+	assert.strictEqual(valid, test.valid, test.description);
+}
+
+// Leaf hash is the same one the module emits in the `Insert` event,
+// so the first sibling in the proof of `a` is the leaf hash of `b`
+assert.strictEqual(merkle.hashLeaf(Buffer.from('b')), fixtures.cases[0].proof[0]);
+
+const root = fixtures.cases[0].root;
+
+// Long proofs are rejected instead of trapping
+const longProof = JSON.stringify(new Array(200).fill(root));
+assert.strictEqual(merkle.verifyProof(longProof, Buffer.from('a'), '0', root), false);
+
+// Indices are not limited to JS numbers, the proof of `a` doesn't hold at index 2^64
+const proofOfA = JSON.stringify(fixtures.cases[0].proof);
+assert.strictEqual(merkle.verifyProof(proofOfA, Buffer.from('a'), '18446744073709551616', root), false);
+
+// Malformed input throws
+assert.throws(() => merkle.verifyProof('not json', Buffer.from('a'), '0', root));
+assert.throws(() => merkle.verifyProof('[]', Buffer.from('a'), '0', '0x1234'));
+assert.throws(() => merkle.verifyProof(proofOfA, Buffer.from('a'), '-1', root));
+
+console.log(`${fixtures.cases.length} fixtures verified`);
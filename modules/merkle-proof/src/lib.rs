@@ -0,0 +1,226 @@
+//! Verification of proofs created for the append-only merkle tree of the `merkle-tree` module.
+//!
+//! Has no dependencies on `std` or substrate, so the same code runs in the runtime, in the client,
+//! in the browser and on embedded devices. Hash function is provided through the `Hasher` trait.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "blake2")]
+extern crate blake2_rfc;
+#[cfg(feature = "blake2")]
+extern crate parity_codec as codec;
+
+#[cfg(feature = "std")]
+use std::fmt;
+
+// Hash functions used to build the tree
+pub trait Hasher {
+    type Out: Copy + PartialEq;
+
+    // Hash of a leaf made from the inserted value
+    fn hash_leaf(value: &[u8]) -> Self::Out;
+    // Hash of the parent of two nodes
+    fn hash_pair(left: &Self::Out, right: &Self::Out) -> Self::Out;
+    // Hash of the parent of a node that doesn't have a sibling
+    fn hash_single(node: &Self::Out) -> Self::Out;
+}
+
+// Reasons for a proof to be rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    // Node index is not smaller than the number of leaves
+    IndexOutOfRange,
+    // Proof has more levels than the tree
+    ProofTooLong,
+    // Proof has less levels than needed to reach the node index
+    ProofTooShort,
+    // Root computed from the proof is different
+    InvalidProof,
+}
+
+impl Error {
+    pub fn as_str(self: &Self) -> &'static str {
+        match self {
+            Error::IndexOutOfRange => "Node index out of range",
+            Error::ProofTooLong => "Proof is too long",
+            Error::ProofTooShort => "Proof is too short",
+            Error::InvalidProof => "Proof not valid",
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for Error {
+    fn fmt(self: &Self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+// Number of levels above the leaves in a tree with `n_nodes` leaves e.g.:
+// 1 - 0
+// 2 - 1
+// 3 - 2
+// 5 - 3
+pub fn tree_height(n_nodes: u128) -> usize {
+    if n_nodes <= 1 {
+        return 0;
+    }
+    (128 - (n_nodes - 1).leading_zeros()) as usize
+}
+
+// Largest number of leaves in a tree with `tree_height` levels above them
+// Used to bound proofs when the number of leaves in the tree is not known
+pub fn max_n_nodes(tree_height: usize) -> u128 {
+    if tree_height >= 128 {
+        return u128::max_value();
+    }
+    1 << tree_height
+}
+
+// Check that a proof with `proof_len` levels can exist for node at `node_index`, while tree has `n_nodes` leaves
+pub fn check_bounds(proof_len: usize, node_index: u128, n_nodes: u128) -> Result<(), Error> {
+    // Node has to be one of the inserted leaves
+    if node_index >= n_nodes {
+        return Err(Error::IndexOutOfRange);
+    }
+    // No tree state is higher than the current one, so proof can't have more levels than it
+    if proof_len > tree_height(n_nodes) {
+        return Err(Error::ProofTooLong);
+    }
+    // Every tree state containing `node_index` needs at least as many levels as bits of the index
+    if proof_len < tree_height(node_index + 1) {
+        return Err(Error::ProofTooShort);
+    }
+    Ok(())
+}
+
+// Compute the root hash made by the leaf at `node_index` and its `proof`
+pub fn compute_root<H: Hasher>(proof: &[Option<H::Out>], leaf_hash: H::Out, node_index: u128) -> H::Out {
+    let mut hash = leaf_hash;
+    for (i, sibling) in proof.iter().enumerate() {
+        hash = match sibling {
+            Some(sibling) => {
+                // Check if node on `i` level is left or right sibling
+                let is_right = node_index.checked_shr(i as u32).unwrap_or(0) & 1 == 1;
+                if is_right {
+                    H::hash_pair(sibling, &hash)
+                } else {
+                    H::hash_pair(&hash, sibling)
+                }
+            },
+            None => H::hash_single(&hash),
+        }
+    }
+    hash
+}
+
+// Prove that `leaf_hash` has index of `node_index` and that it exists inside `root_hash` state
+// `n_nodes` is the number of leaves in the newest tree state
+pub fn verify_proof<H: Hasher>(
+    proof: &[Option<H::Out>],
+    leaf_hash: H::Out,
+    node_index: u128,
+    n_nodes: u128,
+    root_hash: H::Out,
+) -> Result<(), Error> {
+    check_bounds(proof.len(), node_index, n_nodes)?;
+    if compute_root::<H>(proof, leaf_hash, node_index) != root_hash {
+        return Err(Error::InvalidProof);
+    }
+    Ok(())
+}
+
+// Blake2b-256 over SCALE encoded data, same as `BlakeTwo256::hash_of` in the runtime
+#[cfg(feature = "blake2")]
+pub struct Blake2Hasher;
+
+#[cfg(feature = "blake2")]
+impl Blake2Hasher {
+    fn hash(data: &[u8]) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        out.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes());
+        out
+    }
+}
+
+#[cfg(feature = "blake2")]
+impl Hasher for Blake2Hasher {
+    type Out = [u8; 32];
+
+    fn hash_leaf(value: &[u8]) -> [u8; 32] {
+        // Value is inserted as `Vec<u8>`, so it is hashed together with its length prefix
+        Self::hash(&codec::Encode::encode(value))
+    }
+
+    fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut pair = [0u8; 64];
+        pair[..32].copy_from_slice(left);
+        pair[32..].copy_from_slice(right);
+        Self::hash(&pair)
+    }
+
+    fn hash_single(node: &[u8; 32]) -> [u8; 32] {
+        Self::hash(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keeps track of which nodes were combined, instead of hashing them
+    struct TestHasher;
+
+    impl Hasher for TestHasher {
+        type Out = u64;
+
+        fn hash_leaf(value: &[u8]) -> u64 {
+            value[0] as u64
+        }
+
+        fn hash_pair(left: &u64, right: &u64) -> u64 {
+            left.wrapping_mul(1000).wrapping_add(*right)
+        }
+
+        fn hash_single(node: &u64) -> u64 {
+            node.wrapping_add(1)
+        }
+    }
+
+    #[test]
+    fn should_compute_root_of_three_leaves() {
+        let a = TestHasher::hash_leaf(&[1]);
+        let b = TestHasher::hash_leaf(&[2]);
+        let c = TestHasher::hash_leaf(&[3]);
+        let ab = TestHasher::hash_pair(&a, &b);
+        let c1 = TestHasher::hash_single(&c);
+        let root = TestHasher::hash_pair(&ab, &c1);
+
+        assert_eq!(verify_proof::<TestHasher>(&[Some(b), Some(c1)], a, 0, 3, root), Ok(()));
+        assert_eq!(verify_proof::<TestHasher>(&[Some(a), Some(c1)], b, 1, 3, root), Ok(()));
+        assert_eq!(verify_proof::<TestHasher>(&[None, Some(ab)], c, 2, 3, root), Ok(()));
+        assert_eq!(verify_proof::<TestHasher>(&[Some(a), Some(c1)], b, 0, 3, root), Err(Error::InvalidProof));
+    }
+
+    #[test]
+    fn should_reject_proofs_out_of_bounds() {
+        assert_eq!(check_bounds(0, 0, 1), Ok(()));
+        assert_eq!(check_bounds(1, 0, 1), Err(Error::ProofTooLong));
+        assert_eq!(check_bounds(200, 0, 2), Err(Error::ProofTooLong));
+        assert_eq!(check_bounds(1, 3, 4), Err(Error::ProofTooShort));
+        assert_eq!(check_bounds(2, 4, 4), Err(Error::IndexOutOfRange));
+        assert_eq!(check_bounds(128, u128::max_value() - 1, u128::max_value()), Ok(()));
+        assert_eq!(check_bounds(3, 7, max_n_nodes(3)), Ok(()));
+        assert_eq!(check_bounds(3, 8, max_n_nodes(3)), Err(Error::IndexOutOfRange));
+        assert_eq!(check_bounds(129, 0, max_n_nodes(129)), Err(Error::ProofTooLong));
+    }
+
+    #[test]
+    fn should_not_panic_on_long_proofs() {
+        let proof = [Some(0u64); 200];
+        compute_root::<TestHasher>(&proof, 1, u128::max_value());
+    }
+}
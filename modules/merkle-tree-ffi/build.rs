@@ -0,0 +1,24 @@
+extern crate cbindgen;
+
+use std::env;
+
+// Generates `merkle_tree.h` from the exported functions into `OUT_DIR`. The committed
+// `include/merkle_tree.h` is only overwritten when `UPDATE_MERKLE_TREE_HEADER` is set
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Unable to generate C header");
+    bindings.write_to_file(format!("{}/merkle_tree.h", out_dir));
+    if env::var_os("UPDATE_MERKLE_TREE_HEADER").is_some() {
+        bindings.write_to_file(format!("{}/include/merkle_tree.h", crate_dir));
+    }
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=UPDATE_MERKLE_TREE_HEADER");
+}
@@ -0,0 +1,62 @@
+#ifndef MERKLE_TREE_H
+#define MERKLE_TREE_H
+
+/* Generated by cbindgen from src/lib.rs, don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum {
+  MERKLE_STATUS_OK = 0,
+  MERKLE_STATUS_NULL_POINTER,
+  MERKLE_STATUS_NOT_FOUND,
+  MERKLE_STATUS_INDEX_OUT_OF_RANGE,
+  MERKLE_STATUS_PROOF_TOO_LONG,
+  MERKLE_STATUS_PROOF_TOO_SHORT,
+  MERKLE_STATUS_INVALID_PROOF,
+  MERKLE_STATUS_PANIC,
+} MerkleStatus;
+
+typedef struct MerkleTree MerkleTree;
+
+typedef struct {
+  uint8_t bytes[32];
+} MerkleHash;
+
+typedef struct {
+  bool has_sibling;
+  MerkleHash sibling;
+} MerkleProofNode;
+
+typedef struct {
+  MerkleProofNode *nodes;
+  uintptr_t len;
+} MerkleProof;
+
+MerkleStatus merkle_hash_leaf(const uint8_t *value, uintptr_t len, MerkleHash *out);
+
+void merkle_proof_free(MerkleProof *proof);
+
+void merkle_tree_free(MerkleTree *tree);
+
+MerkleTree *merkle_tree_from_leaves(const MerkleHash *leaves, uintptr_t n_leaves);
+
+uint64_t merkle_tree_n_nodes(const MerkleTree *tree);
+
+MerkleStatus merkle_tree_proof(const MerkleTree *tree,
+                               uint64_t node_index,
+                               const MerkleHash *root,
+                               MerkleProof *out);
+
+MerkleStatus merkle_tree_root(const MerkleTree *tree, MerkleHash *out);
+
+MerkleStatus merkle_verify_proof(const MerkleProofNode *nodes,
+                                 uintptr_t len,
+                                 const MerkleHash *leaf,
+                                 uint64_t node_index,
+                                 uint64_t n_nodes,
+                                 const MerkleHash *root);
+
+#endif /* MERKLE_TREE_H */
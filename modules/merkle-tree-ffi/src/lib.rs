@@ -0,0 +1,221 @@
+//! C ABI over `MerkleTreeClient`, for building the tree from leaf hashes, creating proofs by index
+//! and verifying them from C, C++, Go or anything else that can call C functions.
+//!
+//! Header is committed as `include/merkle_tree.h` and regenerated by building with `UPDATE_MERKLE_TREE_HEADER`
+//! set. Every buffer returned by the library is owned by the caller and has to be released with the matching
+//! `*_free` function, never with `free`.
+
+extern crate merkle_proof;
+extern crate merkle_tree;
+extern crate substrate_primitives;
+
+use std::panic::{self, UnwindSafe};
+use std::ptr;
+use std::slice;
+
+use merkle_tree::{ClientHasher, MerkleTreeClient};
+use substrate_primitives::H256;
+
+// Tree built from leaf hashes, owned by the caller
+pub struct MerkleTree(MerkleTreeClient);
+
+// 32 byte hash of a leaf or inner node
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MerkleHash {
+    pub bytes: [u8; 32],
+}
+
+// Sibling on a single level of the proof
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MerkleProofNode {
+    // `false` if the node doesn't have a sibling on this level and is hashed with itself
+    pub has_sibling: bool,
+    pub sibling: MerkleHash,
+}
+
+// Proof buffer owned by the caller, released with `merkle_proof_free`
+#[repr(C)]
+pub struct MerkleProof {
+    pub nodes: *mut MerkleProofNode,
+    pub len: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MerkleStatus {
+    Ok = 0,
+    // Required pointer argument was null
+    NullPointer,
+    // Tree is empty, or there is no tree state or node for the given root and index
+    NotFound,
+    // Node index is not smaller than the number of leaves
+    IndexOutOfRange,
+    // Proof has more levels than the tree
+    ProofTooLong,
+    // Proof has less levels than needed to reach the node index
+    ProofTooShort,
+    // Root computed from the proof is different
+    InvalidProof,
+    // Library panicked, the output arguments are left untouched
+    Panic,
+}
+
+impl From<merkle_proof::Error> for MerkleStatus {
+    fn from(error: merkle_proof::Error) -> MerkleStatus {
+        match error {
+            merkle_proof::Error::IndexOutOfRange => MerkleStatus::IndexOutOfRange,
+            merkle_proof::Error::ProofTooLong => MerkleStatus::ProofTooLong,
+            merkle_proof::Error::ProofTooShort => MerkleStatus::ProofTooShort,
+            merkle_proof::Error::InvalidProof => MerkleStatus::InvalidProof,
+        }
+    }
+}
+
+// Panics can't unwind into the caller's frames
+fn guard<F: FnOnce() -> MerkleStatus + UnwindSafe>(f: F) -> MerkleStatus {
+    panic::catch_unwind(f).unwrap_or(MerkleStatus::Panic)
+}
+
+// Build a tree from `n_leaves` leaf hashes, in the order they were inserted
+// Returns null on failure, the tree is released with `merkle_tree_free`
+#[no_mangle]
+pub unsafe extern "C" fn merkle_tree_from_leaves(leaves: *const MerkleHash, n_leaves: usize) -> *mut MerkleTree {
+    if leaves.is_null() && n_leaves > 0 {
+        return ptr::null_mut();
+    }
+    let result = panic::catch_unwind(|| {
+        let leaves: &[MerkleHash] = if n_leaves == 0 { &[] } else { slice::from_raw_parts(leaves, n_leaves) };
+        // Snapshots are disabled, the tree only lives in memory
+        MerkleTreeClient::from_leaves(leaves.iter().map(|leaf| H256::from(leaf.bytes)), None)
+    });
+    match result {
+        Ok(Ok(client)) => Box::into_raw(Box::new(MerkleTree(client))),
+        _ => ptr::null_mut(),
+    }
+}
+
+// Release the tree, null is ignored
+#[no_mangle]
+pub unsafe extern "C" fn merkle_tree_free(tree: *mut MerkleTree) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+// Number of leaves in the tree, 0 for null
+#[no_mangle]
+pub unsafe extern "C" fn merkle_tree_n_nodes(tree: *const MerkleTree) -> u64 {
+    match tree.as_ref() {
+        Some(tree) => tree.0.n_nodes as u64,
+        None => 0,
+    }
+}
+
+// Write the current root hash into `out`
+#[no_mangle]
+pub unsafe extern "C" fn merkle_tree_root(tree: *const MerkleTree, out: *mut MerkleHash) -> MerkleStatus {
+    let (tree, out) = match (tree.as_ref(), out.as_mut()) {
+        (Some(tree), Some(out)) => (tree, out),
+        _ => return MerkleStatus::NullPointer,
+    };
+    match tree.0.root_hash {
+        Some(root_hash) => {
+            out.bytes = root_hash.0;
+            MerkleStatus::Ok
+        },
+        None => MerkleStatus::NotFound,
+    }
+}
+
+// Write the proof for leaf at `node_index` inside the tree state with `root` into `out`
+// `root` can be any root the tree had, `out` is released with `merkle_proof_free`
+#[no_mangle]
+pub unsafe extern "C" fn merkle_tree_proof(
+    tree: *const MerkleTree,
+    node_index: u64,
+    root: *const MerkleHash,
+    out: *mut MerkleProof,
+) -> MerkleStatus {
+    let (tree, root, out) = match (tree.as_ref(), root.as_ref(), out.as_mut()) {
+        (Some(tree), Some(root), Some(out)) => (tree, root, out),
+        _ => return MerkleStatus::NullPointer,
+    };
+    guard(panic::AssertUnwindSafe(|| {
+        let proof = match tree.0.get_proof_for_index(node_index as u128, H256::from(root.bytes)) {
+            Ok(proof) => proof,
+            Err(_) => return MerkleStatus::NotFound,
+        };
+        let nodes: Box<[MerkleProofNode]> = proof.iter().map(|sibling| MerkleProofNode {
+            has_sibling: sibling.is_some(),
+            sibling: MerkleHash { bytes: sibling.unwrap_or_default().0 },
+        }).collect();
+        out.len = nodes.len();
+        out.nodes = Box::into_raw(nodes) as *mut MerkleProofNode;
+        MerkleStatus::Ok
+    }))
+}
+
+// Release the nodes of a proof created by `merkle_tree_proof` and reset it, null is ignored
+#[no_mangle]
+pub unsafe extern "C" fn merkle_proof_free(proof: *mut MerkleProof) {
+    let proof = match proof.as_mut() {
+        Some(proof) => proof,
+        None => return,
+    };
+    if !proof.nodes.is_null() {
+        drop(Box::from_raw(slice::from_raw_parts_mut(proof.nodes, proof.len)));
+    }
+    proof.nodes = ptr::null_mut();
+    proof.len = 0;
+}
+
+// Write the hash of the leaf made from `len` bytes of `value` into `out`, same as in the `Insert` event
+#[no_mangle]
+pub unsafe extern "C" fn merkle_hash_leaf(value: *const u8, len: usize, out: *mut MerkleHash) -> MerkleStatus {
+    if value.is_null() && len > 0 {
+        return MerkleStatus::NullPointer;
+    }
+    let out = match out.as_mut() {
+        Some(out) => out,
+        None => return MerkleStatus::NullPointer,
+    };
+    guard(panic::AssertUnwindSafe(|| {
+        let value: &[u8] = if len == 0 { &[] } else { slice::from_raw_parts(value, len) };
+        out.bytes = ClientHasher::hash_leaf(value).0;
+        MerkleStatus::Ok
+    }))
+}
+
+// Prove that `leaf` has index of `node_index` and that it exists inside the tree state with `root`
+// `n_nodes` is the number of leaves in the newest tree state, doesn't need the tree itself
+#[no_mangle]
+pub unsafe extern "C" fn merkle_verify_proof(
+    nodes: *const MerkleProofNode,
+    len: usize,
+    leaf: *const MerkleHash,
+    node_index: u64,
+    n_nodes: u64,
+    root: *const MerkleHash,
+) -> MerkleStatus {
+    if nodes.is_null() && len > 0 {
+        return MerkleStatus::NullPointer;
+    }
+    let (leaf, root) = match (leaf.as_ref(), root.as_ref()) {
+        (Some(leaf), Some(root)) => (leaf, root),
+        _ => return MerkleStatus::NullPointer,
+    };
+    guard(panic::AssertUnwindSafe(|| {
+        let nodes: &[MerkleProofNode] = if len == 0 { &[] } else { slice::from_raw_parts(nodes, len) };
+        let proof: Vec<Option<H256>> = nodes.iter().map(|node| {
+            if node.has_sibling { Some(H256::from(node.sibling.bytes)) } else { None }
+        }).collect();
+        let leaf_hash = H256::from(leaf.bytes);
+        let root_hash = H256::from(root.bytes);
+        match merkle_proof::verify_proof::<ClientHasher>(&proof, leaf_hash, node_index as u128, n_nodes as u128, root_hash) {
+            Ok(()) => MerkleStatus::Ok,
+            Err(e) => e.into(),
+        }
+    }))
+}
@@ -0,0 +1,21 @@
+#!/usr/bin/env bash
+
+set -e
+
+PROJECT_ROOT="$( cd "$( dirname "${BASH_SOURCE[0]}" )" >/dev/null && pwd )"
+TARGET_DIR="$PROJECT_ROOT/../../target/release"
+
+cd "$PROJECT_ROOT"
+
+cargo build --release
+
+# Committed header has to match the one generated by the build, `UPDATE_MERKLE_TREE_HEADER=1 cargo build` updates it
+GENERATED_HEADER=$(ls -t "$TARGET_DIR"/build/merkle-tree-ffi-*/out/merkle_tree.h | head -n 1)
+if ! diff -u include/merkle_tree.h "$GENERATED_HEADER"; then
+	echo "include/merkle_tree.h is out of date"
+	exit 1
+fi
+
+cc -std=c99 -Wall -Werror -o "$TARGET_DIR/merkle_tree_test" tests/test.c \
+	-Iinclude "$TARGET_DIR/libmerkle_tree_ffi.a" -lpthread -ldl -lm
+"$TARGET_DIR/merkle_tree_test"
@@ -0,0 +1,82 @@
+/* Builds a tree through the C ABI and checks proofs of every leaf in every tree state. */
+
+#include <assert.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "merkle_tree.h"
+
+#define N_LEAVES 37
+
+int main(void) {
+  MerkleHash leaves[N_LEAVES];
+  MerkleHash roots[N_LEAVES];
+
+  for (int i = 0; i < N_LEAVES; i++) {
+    uint8_t value[2] = {'a', (uint8_t)i};
+    assert(merkle_hash_leaf(value, sizeof(value), &leaves[i]) == MERKLE_STATUS_OK);
+  }
+
+  /* Root of every earlier state, to check proofs against previous roots */
+  for (int n = 1; n <= N_LEAVES; n++) {
+    MerkleTree *tree = merkle_tree_from_leaves(leaves, n);
+    assert(tree != NULL);
+    assert(merkle_tree_n_nodes(tree) == (uint64_t)n);
+    assert(merkle_tree_root(tree, &roots[n - 1]) == MERKLE_STATUS_OK);
+    merkle_tree_free(tree);
+  }
+
+  MerkleTree *tree = merkle_tree_from_leaves(leaves, N_LEAVES);
+  assert(tree != NULL);
+
+  for (int n = 1; n <= N_LEAVES; n++) {
+    for (int i = 0; i < n; i++) {
+      MerkleProof proof = {NULL, 0};
+      assert(merkle_tree_proof(tree, i, &roots[n - 1], &proof) == MERKLE_STATUS_OK);
+      assert(merkle_verify_proof(proof.nodes, proof.len, &leaves[i], i, N_LEAVES, &roots[n - 1]) ==
+             MERKLE_STATUS_OK);
+
+      /* Wrong leaf or root is rejected */
+      MerkleStatus status = merkle_verify_proof(proof.nodes, proof.len, &leaves[(i + 1) % N_LEAVES], i,
+                                                N_LEAVES, &roots[n - 1]);
+      assert(status == MERKLE_STATUS_INVALID_PROOF);
+      status = merkle_verify_proof(proof.nodes, proof.len, &leaves[i], i, N_LEAVES, &roots[n % N_LEAVES]);
+      assert(status != MERKLE_STATUS_OK);
+
+      merkle_proof_free(&proof);
+      assert(proof.nodes == NULL && proof.len == 0);
+      /* Freeing twice is harmless */
+      merkle_proof_free(&proof);
+    }
+  }
+
+  /* Leaf that is not in the tree state */
+  MerkleProof proof = {NULL, 0};
+  assert(merkle_tree_proof(tree, 5, &roots[2], &proof) == MERKLE_STATUS_NOT_FOUND);
+  assert(proof.nodes == NULL);
+
+  /* Bounds are checked before hashing */
+  MerkleProofNode long_proof[40];
+  memset(long_proof, 0, sizeof(long_proof));
+  assert(merkle_verify_proof(long_proof, 40, &leaves[0], 0, N_LEAVES, &roots[N_LEAVES - 1]) ==
+         MERKLE_STATUS_PROOF_TOO_LONG);
+  assert(merkle_verify_proof(long_proof, 1, &leaves[0], N_LEAVES, N_LEAVES, &roots[N_LEAVES - 1]) ==
+         MERKLE_STATUS_INDEX_OUT_OF_RANGE);
+
+  /* Null pointers are reported instead of dereferenced */
+  assert(merkle_tree_root(NULL, &roots[0]) == MERKLE_STATUS_NULL_POINTER);
+  assert(merkle_tree_proof(tree, 0, NULL, &proof) == MERKLE_STATUS_NULL_POINTER);
+  assert(merkle_tree_from_leaves(NULL, 1) == NULL);
+
+  /* Empty tree has no root */
+  MerkleTree *empty = merkle_tree_from_leaves(NULL, 0);
+  assert(empty != NULL);
+  assert(merkle_tree_root(empty, &roots[0]) == MERKLE_STATUS_NOT_FOUND);
+  merkle_tree_free(empty);
+
+  merkle_tree_free(tree);
+  merkle_tree_free(NULL);
+
+  printf("C ABI tests passed\n");
+  return 0;
+}
@@ -0,0 +1,260 @@
+//! Blocks full of inserts, with the tree updated on every insert and with inserts aggregated in
+//! `on_finalise`. Besides the time of a block, the number of storage reads and writes of a block is printed.
+//! Edge nodes stored by level are compared with the single vector of earlier versions, counting only the
+//! accesses to the edge node storage of an insert.
+
+#[macro_use]
+extern crate criterion;
+extern crate merkle_tree;
+extern crate parity_codec as codec;
+extern crate sr_io as runtime_io;
+extern crate sr_primitives as runtime_primitives;
+#[macro_use]
+extern crate srml_support;
+extern crate srml_system as system;
+extern crate srml_balances as balances;
+extern crate substrate_primitives as primitives;
+
+#[path = "../src/mock.rs"]
+#[macro_use]
+mod mock;
+
+use std::cell::Cell;
+use std::collections::HashSet;
+
+use criterion::Criterion;
+use codec::Encode;
+use runtime_io::{with_externalities, Externalities, TestExternalities};
+use runtime_primitives::traits::{BlakeTwo256, Hash, OnFinalise};
+use srml_support::storage;
+use primitives::{twox_128, Blake2Hasher, H256};
+
+// Runtime with the given insert mode
+macro_rules! bench_runtime {
+	($name:ident, $aggregate:expr) => {
+		mod $name {
+			use super::*;
+
+			mock_runtime!(Runtime, Vec<u8>, aggregate: $aggregate, store: false, unique: false);
+
+			// Block number `number` with `n_inserts` inserts
+			pub fn block(number: u64, n_inserts: u32) {
+				System::initialise(&number, &Default::default(), &Default::default());
+				for i in 0..n_inserts {
+					MerkleTree::insert(Origin::signed(1), i.to_string().into_bytes()).unwrap();
+				}
+				<MerkleTree as OnFinalise<u64>>::on_finalise(number);
+				System::finalise();
+			}
+		}
+	};
+}
+
+bench_runtime!(immediate, false);
+bench_runtime!(aggregated, true);
+
+// Externalities counting the storage reads and writes made through them, only of `keys` when set
+struct CountingExternalities {
+	inner: TestExternalities<Blake2Hasher>,
+	keys: Option<HashSet<Vec<u8>>>,
+	reads: Cell<usize>,
+	writes: usize,
+	bytes_written: usize,
+}
+
+impl CountingExternalities {
+	fn new(inner: TestExternalities<Blake2Hasher>, keys: Option<HashSet<Vec<u8>>>) -> Self {
+		CountingExternalities { inner, keys, reads: Cell::new(0), writes: 0, bytes_written: 0 }
+	}
+
+	fn counts(&self, key: &[u8]) -> bool {
+		self.keys.as_ref().map_or(true, |keys| keys.contains(key))
+	}
+}
+
+impl Externalities<Blake2Hasher> for CountingExternalities {
+	fn storage(&self, key: &[u8]) -> Option<Vec<u8>> {
+		if self.counts(key) {
+			self.reads.set(self.reads.get() + 1);
+		}
+		self.inner.storage(key)
+	}
+
+	fn child_storage(&self, storage_key: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+		self.inner.child_storage(storage_key, key)
+	}
+
+	fn kill_child_storage(&mut self, storage_key: &[u8]) {
+		self.writes += self.keys.is_none() as usize;
+		self.inner.kill_child_storage(storage_key)
+	}
+
+	fn clear_prefix(&mut self, prefix: &[u8]) {
+		self.writes += self.keys.is_none() as usize;
+		self.inner.clear_prefix(prefix)
+	}
+
+	fn place_storage(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+		if self.counts(&key) {
+			self.writes += 1;
+			self.bytes_written += value.as_ref().map_or(0, |value| value.len());
+		}
+		self.inner.place_storage(key, value)
+	}
+
+	fn place_child_storage(&mut self, storage_key: Vec<u8>, key: Vec<u8>, value: Option<Vec<u8>>) -> bool {
+		self.writes += self.keys.is_none() as usize;
+		self.inner.place_child_storage(storage_key, key, value)
+	}
+
+	fn chain_id(&self) -> u64 {
+		self.inner.chain_id()
+	}
+
+	fn storage_root(&mut self) -> H256 {
+		self.inner.storage_root()
+	}
+
+	fn child_storage_root(&mut self, storage_key: &[u8]) -> Option<Vec<u8>> {
+		self.inner.child_storage_root(storage_key)
+	}
+
+	fn storage_changes_root(&mut self, block: u64) -> Option<H256> {
+		self.inner.storage_changes_root(block)
+	}
+}
+
+// Storage reads and writes of the second block, so the first inserts into an empty tree are not counted
+fn count_accesses(new_test_ext: fn() -> TestExternalities<Blake2Hasher>, block: fn(u64, u32), n_inserts: u32) -> (usize, usize) {
+	let mut ext = CountingExternalities::new(new_test_ext(), None);
+	with_externalities(&mut ext, || block(1, n_inserts));
+	ext.reads.set(0);
+	ext.writes = 0;
+	with_externalities(&mut ext, || block(2, n_inserts));
+	(ext.reads.get(), ext.writes)
+}
+
+// Raw key of level `level` of `EdgeNodeAt`
+fn edge_node_key(level: u32) -> Vec<u8> {
+	let mut key = b"MerkleTree EdgeNodeAt".to_vec();
+	level.encode_to(&mut key);
+	key
+}
+
+// Storage keys of the edge nodes in both layouts, hashed the way the runtime storage hashes them
+fn edge_keys() -> HashSet<Vec<u8>> {
+	let mut keys: HashSet<Vec<u8>> = (0..128).map(|level| twox_128(&edge_node_key(level)).to_vec()).collect();
+	keys.insert(twox_128(b"MerkleTree EdgeLevels").to_vec());
+	keys.insert(twox_128(b"MerkleTree EdgeNodes").to_vec());
+	keys
+}
+
+// Tree of `n_nodes` leaves with its edge nodes stored as a single vector, or by level
+fn edge_state(n_nodes: u128, vector: bool) -> TestExternalities<Blake2Hasher> {
+	let mut ext = immediate::new_test_ext();
+	with_externalities(&mut ext, || {
+		// Levels of the bits set in `n_nodes` have an edge node
+		let edge_nodes: Vec<Option<H256>> = (0..128 - n_nodes.leading_zeros())
+			.map(|level| if (n_nodes >> level) & 1 == 1 { Some(H256::from([level as u8; 32])) } else { None })
+			.collect();
+		storage::put(b"MerkleTree NNodes", &n_nodes);
+		if vector {
+			storage::put(b"MerkleTree EdgeNodes", &edge_nodes);
+		} else {
+			for (level, edge_node) in edge_nodes.iter().enumerate() {
+				if let Some(hash) = edge_node {
+					storage::put(&edge_node_key(level as u32), hash);
+				}
+			}
+			storage::put(b"MerkleTree EdgeLevels", &(edge_nodes.len() as u32));
+		}
+	});
+	ext
+}
+
+// Insert of leaf `leaf` as done by versions storing the edge nodes as a single vector
+fn vector_insert(leaf: H256) {
+	let n_nodes: u128 = storage::get(b"MerkleTree NNodes").unwrap_or(0);
+	let mut edge_nodes: Vec<Option<H256>> = storage::get(b"MerkleTree EdgeNodes").unwrap_or_default();
+	// New edge node is on the level of the lowest bit not set in `n_nodes`, the levels below it are emptied
+	let edge_level = (!n_nodes).trailing_zeros() as usize;
+	let mut node = leaf;
+	let mut new_edge = leaf;
+	for level in 0..edge_nodes.len() {
+		node = match edge_nodes[level] {
+			Some(edge_node) => BlakeTwo256::hash_of(&[edge_node, node]),
+			None => BlakeTwo256::hash_of(&node),
+		};
+		if level + 1 == edge_level {
+			new_edge = node;
+		}
+	}
+	if edge_level >= edge_nodes.len() {
+		edge_nodes.push(Some(new_edge));
+	} else {
+		edge_nodes[edge_level] = Some(new_edge);
+	}
+	for edge_node in edge_nodes[..edge_level].iter_mut() {
+		*edge_node = None;
+	}
+	storage::put(b"MerkleTree RootHash", &node);
+	storage::put(b"MerkleTree NNodes", &(n_nodes + 1));
+	storage::put(b"MerkleTree EdgeNodes", &edge_nodes);
+}
+
+// Edge storage reads, writes and bytes written per insert, averaged over `n_inserts` inserts into a
+// tree of `n_nodes` leaves
+fn count_edge_accesses(n_nodes: u128, vector: bool, n_inserts: u32) -> (f64, f64, f64) {
+	let mut ext = CountingExternalities::new(edge_state(n_nodes, vector), Some(edge_keys()));
+	with_externalities(&mut ext, || for i in 0..n_inserts {
+		let value = i.to_string().into_bytes();
+		if vector {
+			vector_insert(BlakeTwo256::hash_of(&value));
+		} else {
+			immediate::MerkleTree::insert(immediate::Origin::signed(1), value).unwrap();
+		}
+	});
+	let per_insert = |count: usize| count as f64 / n_inserts as f64;
+	(per_insert(ext.reads.get()), per_insert(ext.writes), per_insert(ext.bytes_written))
+}
+
+fn edge_layouts(c: &mut Criterion) {
+	for n_nodes in [1_000u128, 1_000_000, 1_000_000_000].iter().cloned() {
+		let (vector_reads, vector_writes, vector_bytes) = count_edge_accesses(n_nodes, true, 1000);
+		let (level_reads, level_writes, level_bytes) = count_edge_accesses(n_nodes, false, 1000);
+		println!(
+			"{} leaves, per insert: {} reads, {} writes and {} bytes written with a vector, {} reads, {} writes and {} bytes written by level",
+			n_nodes, vector_reads, vector_writes, vector_bytes, level_reads, level_writes, level_bytes,
+		);
+
+		c.bench_function(&format!("edge vector {}", n_nodes), move |b| b.iter(|| {
+			with_externalities(&mut edge_state(n_nodes, true), || vector_insert(H256::zero()))
+		}));
+		c.bench_function(&format!("edge levels {}", n_nodes), move |b| b.iter(|| {
+			with_externalities(&mut edge_state(n_nodes, false), || {
+				immediate::MerkleTree::insert(immediate::Origin::signed(1), vec![0]).unwrap()
+			})
+		}));
+	}
+}
+
+fn insert_blocks(c: &mut Criterion) {
+	for n_inserts in [1u32, 10, 100, 500].iter().cloned() {
+		let (immediate_reads, immediate_writes) = count_accesses(immediate::new_test_ext, immediate::block, n_inserts);
+		let (aggregated_reads, aggregated_writes) = count_accesses(aggregated::new_test_ext, aggregated::block, n_inserts);
+		println!(
+			"{} inserts per block: {} reads and {} writes when updating the tree on every insert, {} reads and {} writes when aggregating",
+			n_inserts, immediate_reads, immediate_writes, aggregated_reads, aggregated_writes,
+		);
+
+		c.bench_function(&format!("immediate {}", n_inserts), move |b| b.iter(|| {
+			with_externalities(&mut immediate::new_test_ext(), || immediate::block(1, n_inserts))
+		}));
+		c.bench_function(&format!("aggregated {}", n_inserts), move |b| b.iter(|| {
+			with_externalities(&mut aggregated::new_test_ext(), || aggregated::block(1, n_inserts))
+		}));
+	}
+}
+
+criterion_group!(benches, insert_blocks, edge_layouts);
+criterion_main!(benches);
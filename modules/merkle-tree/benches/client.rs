@@ -0,0 +1,54 @@
+//! Client tree of a million leaves. Time and memory of building it are printed once, proofs
+//! against its latest state and against an earlier state are benched.
+
+#[macro_use]
+extern crate criterion;
+extern crate merkle_tree;
+extern crate sr_primitives as runtime_primitives;
+
+use std::rc::Rc;
+use std::time::Instant;
+
+use criterion::Criterion;
+use merkle_tree::MerkleTreeClient;
+use runtime_primitives::traits::{BlakeTwo256, Hash};
+
+const N_NODES: u64 = 1_000_000;
+
+fn proofs(c: &mut Criterion) {
+	let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+	let started = Instant::now();
+	client_tree.append_leaves((0..N_NODES).map(|i| BlakeTwo256::hash_of(&i)).collect());
+	let footprint = client_tree.memory_footprint();
+	println!(
+		"{} leaves: built in {:?}, {} MB in memory, {} bytes per leaf",
+		N_NODES,
+		started.elapsed(),
+		footprint / 1_000_000,
+		footprint as u64 / N_NODES,
+	);
+
+	let client_tree = Rc::new(client_tree);
+	let latest_tree = client_tree.clone();
+	let root_hash = client_tree.root_hash.unwrap();
+	c.bench_function("proof of latest state", move |b| {
+		let mut node_index = 0;
+		b.iter(|| {
+			node_index = (node_index + 7919) % N_NODES as u128;
+			latest_tree.get_proof_for_index(node_index, root_hash).unwrap()
+		})
+	});
+
+	// Earlier states are rebuilt from the leaves, as there are no snapshots
+	let earlier_root = client_tree.get_root_at(N_NODES as u128 / 2).unwrap();
+	c.bench_function("proof of earlier state", move |b| {
+		let mut node_index = 0;
+		b.iter(|| {
+			node_index = (node_index + 7919) % (N_NODES as u128 / 2);
+			client_tree.get_proof_for_index(node_index, earlier_root).unwrap()
+		})
+	});
+}
+
+criterion_group!(benches, proofs);
+criterion_main!(benches);
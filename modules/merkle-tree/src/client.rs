@@ -1,27 +1,103 @@
 use std::collections::HashMap;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
+use std::marker::PhantomData;
+use std::mem;
 
+extern crate merkle_proof;
+extern crate rayon;
 extern crate serde;
 extern crate serde_json;
 extern crate sr_primitives;
 extern crate substrate_primitives;
 
+use codec::Encode;
+use merkle_proof::Hasher;
+use rayon::prelude::*;
 use sr_primitives::traits::BlakeTwo256;
 use runtime_primitives::traits::Hash;
 use substrate_primitives::H256;
 
 type Proof = Vec<Option<H256>>;
 type EdgeNodes = Vec<Option<H256>>;
-pub type Tree = HashMap<H256, Node>;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Node {
-    pub parent: H256,
-    pub sibling: Option<H256>
+// Fields of the `Insert` event: index of the leaf, hash of the leaf and root hash after the insert
+pub type InsertEvent = (u128, H256, H256);
+
+// Event that doesn't follow from the client's tree, so the client diverged from the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncDivergence {
+    // Event is not for the next leaf, an event was missed or applied twice
+    UnexpectedIndex,
+    // Root computed by the client is different from the root of the event
+    RootMismatch,
+    // Leaf is already in the tree, while leaves have to be unique
+    DuplicateLeaf,
+}
+
+impl SyncDivergence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncDivergence::UnexpectedIndex => "Event is not for the next leaf!",
+            SyncDivergence::RootMismatch => "Root of the event doesn't match the client!",
+            SyncDivergence::DuplicateLeaf => "Leaf of the event is already in the tree!",
+        }
+    }
+}
+
+// Hashing of the tree nodes, same as in the module with `BlakeTwo256`
+pub struct ClientHasher;
+
+impl ClientHasher {
+    // Hash of a leaf of any type, made from its encoding the same way the module hashes `T::Leaf`
+    // Bytes are encoded with their length prefix, the same as a `Vec<u8>` leaf
+    pub fn hash_leaf<L: Encode + ?Sized>(leaf: &L) -> H256 {
+        BlakeTwo256::hash(&leaf.encode())
+    }
+}
+
+impl Hasher for ClientHasher {
+    type Out = H256;
+
+    // Leaf made from raw bytes, a `Vec<u8>` leaf
+    fn hash_leaf(value: &[u8]) -> H256 {
+        ClientHasher::hash_leaf(value)
+    }
+
+    fn hash_pair(left: &H256, right: &H256) -> H256 {
+        BlakeTwo256::hash_of(&[*left, *right])
+    }
+
+    fn hash_single(node: &H256) -> H256 {
+        BlakeTwo256::hash_of(node)
+    }
 }
 
-pub struct MerkleTreeClient {
+// Tree state the client can be rolled back to
+pub enum RollbackTarget {
+    // State right after all the inserts from the block were applied
+    Block(H256),
+    // State containing only the first `n` leaf nodes
+    NNodes(u128),
+}
+
+// Inserts applied from a single block
+struct BlockRecord {
+    block_hash: H256,
+    // Number of leaf nodes after the block was applied
+    n_nodes: u128,
+}
+
+// Last node on every level of a tree state, leaves first, saved per root when snapshots are cached
+// Only these nodes of an earlier state differ from the current tree, so proofs against it don't hash them again
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+struct Snapshot {
+    n_nodes: u128,
+    right_edge: Vec<H256>,
+}
+
+// Leaves are made from values of type `L`, the `Leaf` type of the module, hashed by their SCALE encoding
+pub struct MerkleTreeClient<L = Vec<u8>> {
     // Current root hash of the tree
     pub root_hash: Option<H256>,
     // Number of leaf nodes
@@ -30,174 +106,478 @@ pub struct MerkleTreeClient {
     pub node_indices: HashMap<H256, u128>,
     // Edge nodes neccessary for the next insert
     pub edge_nodes: EdgeNodes,
-    // Every node in tree and its connections
-    pub tree: Tree,
+    // Hashes of all leaf nodes in order of insertion
+    pub leaves: Vec<H256>,
+    // Hashes of the inner nodes level by level, starting with the parents of the leaves
+    // Node `i` on a level is the parent of nodes `2i` and `2i + 1` on the level below
+    levels: Vec<Vec<H256>>,
+    // Number of leaf nodes in the tree state with the given root hash
+    root_sizes: HashMap<H256, u128>,
+    // Blocks applied so far, in order of application
+    blocks: Vec<BlockRecord>,
+    // Folder where the checkpoint and the snapshots of the tree are saved, `None` disables both
+    snapshot_dir: Option<String>,
+    // Save a snapshot of every tree state in the snapshot folder, as a cache for proofs against it
+    pub cache_snapshots: bool,
+    // Events of leaves that are already in the tree are rejected, same as `UNIQUE_LEAVES` of the module
+    pub unique_leaves: bool,
+    leaf_type: PhantomData<L>,
 }
 
 impl MerkleTreeClient {
-    pub fn new() -> MerkleTreeClient {
+    // Saves the checkpoint and a snapshot of every tree state inside `snapshot_dir`
+    pub fn new(snapshot_dir: String) -> MerkleTreeClient {
+        let mut client = MerkleTreeClient::with_snapshot_dir(Some(snapshot_dir));
+        client.cache_snapshots = true;
+        client
+    }
+
+    // Checkpoints are only needed to resume syncing, every tree state is kept in memory
+    // Snapshots are only a cache and are not saved unless `cache_snapshots` is set
+    pub fn with_snapshot_dir(snapshot_dir: Option<String>) -> MerkleTreeClient {
         MerkleTreeClient {
             root_hash: None,
             n_nodes: 0,
             node_indices: HashMap::new(),
             edge_nodes: vec![],
-            tree: HashMap::new(),
+            leaves: vec![],
+            levels: vec![],
+            root_sizes: HashMap::new(),
+            blocks: vec![],
+            snapshot_dir,
+            cache_snapshots: false,
+            unique_leaves: false,
+            leaf_type: PhantomData,
+        }
+    }
+
+    // Continues syncing with on-chain tree from the checkpoint saved inside `snapshot_dir`
+    pub fn from_checkpoint(snapshot_dir: String) -> Result<MerkleTreeClient, &'static str> {
+        let mut client = MerkleTreeClient::with_snapshot_dir(Some(snapshot_dir));
+        let leaves = client.load_checkpoint()?;
+        client.append_leaves(leaves);
+        Ok(client)
+    }
+
+    // Builds the tree from all the leaves at once, hashing every level in parallel
+    // Gives the same tree as inserting leaves one by one, but saves only one checkpoint at the end and no snapshots
+    pub fn from_leaves<I: IntoIterator<Item = H256>>(leaves: I, snapshot_dir: Option<String>) -> Result<MerkleTreeClient, &'static str> {
+        let mut client = MerkleTreeClient::with_snapshot_dir(snapshot_dir);
+        client.leaves = leaves.into_iter().collect();
+        let n_nodes = client.leaves.len();
+
+        // Every level only depends on the level below it
+        let mut level = 0;
+        while Self::level_width(n_nodes, level) > 1 {
+            let parents: Vec<H256> = client.level(level).par_chunks(2).map(|pair| match pair {
+                [left, right] => BlakeTwo256::hash_of(&[*left, *right]),
+                _ => BlakeTwo256::hash_of(&pair[0]),
+            }).collect();
+            client.levels.push(parents);
+            level += 1;
+        }
+
+        // Every previous state can still be used for proofs, so all of their roots are indexed
+        let roots: Vec<H256> = (1..n_nodes + 1).into_par_iter().map(|size| client.root_at(size)).collect();
+        client.root_sizes = roots.into_iter().enumerate().map(|(index, root_hash)| (root_hash, index as u128 + 1)).collect();
+        client.node_indices = client.leaves.iter().enumerate().map(|(index, leaf)| (*leaf, index as u128)).collect();
+
+        client.n_nodes = n_nodes as u128;
+        client.root_hash = client.level(level).first().cloned();
+        client.edge_nodes = client.current_edge_nodes();
+        if client.snapshot_dir.is_some() {
+            client.save_checkpoint()?;
+        }
+        Ok(client)
+    }
+}
+
+impl<L: Encode> MerkleTreeClient<L> {
+    // Same tree with leaves made from values of type `M`, for modules whose `Leaf` is not `Vec<u8>`
+    pub fn with_leaf_type<M: Encode>(self: Self) -> MerkleTreeClient<M> {
+        MerkleTreeClient {
+            root_hash: self.root_hash,
+            n_nodes: self.n_nodes,
+            node_indices: self.node_indices,
+            edge_nodes: self.edge_nodes,
+            leaves: self.leaves,
+            levels: self.levels,
+            root_sizes: self.root_sizes,
+            blocks: self.blocks,
+            snapshot_dir: self.snapshot_dir,
+            cache_snapshots: self.cache_snapshots,
+            unique_leaves: self.unique_leaves,
+            leaf_type: PhantomData,
         }
     }
 
     // Builds the whole tree with events emitted by the module
-    pub fn build_tree_from_events(self: &mut Self, events: Vec<H256>) {
-        for event_record in events.iter() {
-            self.insert(*event_record);
+    // Every event is checked against the index and root computed by the client. If one doesn't match,
+    // none of the events are applied
+    pub fn build_tree_from_events(self: &mut Self, events: Vec<InsertEvent>) -> Result<(), SyncDivergence> {
+        let n_nodes = self.n_nodes;
+        for (leaf_index, leaf_hash, new_root) in events {
+            if leaf_index != self.n_nodes {
+                self.rollback_to(RollbackTarget::NNodes(n_nodes)).expect("Tree only grew since; qed");
+                return Err(SyncDivergence::UnexpectedIndex);
+            }
+            if self.unique_leaves && self.node_indices.contains_key(&leaf_hash) {
+                self.rollback_to(RollbackTarget::NNodes(n_nodes)).expect("Tree only grew since; qed");
+                return Err(SyncDivergence::DuplicateLeaf);
+            }
+            self.insert(leaf_hash);
+            if self.root_hash != Some(new_root) {
+                self.rollback_to(RollbackTarget::NNodes(n_nodes)).expect("Tree only grew since; qed");
+                return Err(SyncDivergence::RootMismatch);
+            }
         }
+        Ok(())
+    }
+
+    // Appends leaves that don't come with events to check them against, like the ones of a checkpoint
+    pub fn append_leaves(self: &mut Self, leaves: Vec<H256>) {
+        for leaf_hash in leaves {
+            self.insert(leaf_hash);
+        }
+    }
+
+    // Applies events emitted by the module inside block `block_hash`
+    // Block is remembered so the client can later roll back to it if the chain switches forks
+    pub fn build_tree_from_block(self: &mut Self, block_hash: H256, events: Vec<InsertEvent>) -> Result<(), SyncDivergence> {
+        self.build_tree_from_events(events)?;
+        self.blocks.push(BlockRecord {
+            block_hash,
+            n_nodes: self.n_nodes,
+        });
+        Ok(())
+    }
+
+    // Reverts the tree to an earlier state, forgetting every insert made after it
+    // Rolling back to a number of nodes in the middle of a block forgets that block as well
+    pub fn rollback_to(self: &mut Self, target: RollbackTarget) -> Result<(), &'static str> {
+        let n_nodes = match target {
+            RollbackTarget::Block(block_hash) => match self.blocks.iter().rev().find(|block| block.block_hash == block_hash) {
+                Some(block) => block.n_nodes,
+                None => return Err("Block not found in client history!"),
+            },
+            RollbackTarget::NNodes(n_nodes) => n_nodes,
+        };
+        if n_nodes > self.n_nodes {
+            return Err("Can't roll back to a state newer than the current one!");
+        }
+
+        // Forget blocks and tree states that are not part of the target state
+        self.blocks.retain(|block| block.n_nodes <= n_nodes);
+        let orphaned_roots: Vec<H256> = self.root_sizes.iter()
+            .filter(|(_, size)| **size > n_nodes)
+            .map(|(root_hash, _)| *root_hash)
+            .collect();
+        for root_hash in orphaned_roots.iter() {
+            self.root_sizes.remove(root_hash);
+            self.remove_snapshot(root_hash);
+        }
+        self.leaves.truncate(n_nodes as usize);
+        // Leaves inserted more than once point to their last remaining index again
+        self.node_indices = self.leaves.iter().enumerate().map(|(index, leaf)| (*leaf, index as u128)).collect();
+
+        // Drop inner nodes that are not part of the target state
+        // Last node on every level may have lost its right child, so it is hashed again
+        let mut level = 0;
+        while Self::level_width(n_nodes as usize, level) > 1 {
+            let width = Self::level_width(n_nodes as usize, level + 1);
+            self.levels[level].truncate(width);
+            let parent_hash = self.hash_children(level, width - 1);
+            self.levels[level][width - 1] = parent_hash;
+            level += 1;
+        }
+        self.levels.truncate(level);
+
+        self.n_nodes = n_nodes;
+        self.root_hash = self.level(level).first().cloned();
+        self.edge_nodes = self.current_edge_nodes();
+        Ok(())
+    }
+
+    // Switches the tree to a new canonical branch
+    // Rolls back to the last state shared with the new branch and applies its blocks in order
+    // Blocks before the first one that diverges stay applied
+    pub fn reorg(self: &mut Self, common_ancestor: RollbackTarget, blocks: Vec<(H256, Vec<InsertEvent>)>) -> Result<(), &'static str> {
+        self.rollback_to(common_ancestor)?;
+        for (block_hash, events) in blocks {
+            self.build_tree_from_block(block_hash, events).map_err(|e| e.as_str())?;
+        }
+        Ok(())
     }
 
     // Get proof that specified `value` is inside the tree state with root of `root_hash`
-    pub fn get_proof_for(self: &Self, value: Vec<u8>, root_hash: H256) -> Result<Proof, &'static str> {
-        let value_hash = BlakeTwo256::hash_of(&value);
-        let proof: Vec<Option<H256>> = vec![];
+    pub fn get_proof_for(self: &Self, value: L, root_hash: H256) -> Result<Proof, &'static str> {
+        self.get_proof_for_hash(BlakeTwo256::hash_of(&value), root_hash)
+    }
+
+    // Get proof that leaf `value_hash` is inside the tree state with root of `root_hash`
+    // Leaves inserted with `insert_hash` are only known by hash, so their proofs are created with this
+    pub fn get_proof_for_hash(self: &Self, value_hash: H256, root_hash: H256) -> Result<Proof, &'static str> {
         // If `value_hash` is equal to `root_hash`, that means that tree only has one node, in which case empty proof is returned
         if value_hash == root_hash {
-            return Ok(proof);
+            return Ok(vec![]);
         }
-        // Load the saved tree at state `root_hash`
-        let tree_result = self.load_snapshot(&root_hash);
-        match tree_result {
-            Ok(tree) => self.find_node(&tree, proof, value_hash, root_hash).ok_or("Proof not found!"),
-            Err(_e) => Err("Node not found in specified tree state!")
+        // Tree state only depends on the first `n_nodes` leaves
+        let n_nodes = self.get_n_nodes_at(&root_hash).ok_or("Node not found in specified tree state!")?;
+        match self.node_indices.get(&value_hash) {
+            Some(node_index) if *node_index < n_nodes => Ok(self.compute_proof(*node_index, n_nodes, self.load_right_edge(&root_hash, n_nodes))),
+            _ => Err("Node not found in specified tree state!"),
         }
     }
 
+    // Get proof that node at `node_index` is inside the tree state with root of `root_hash`
+    pub fn get_proof_for_index(self: &Self, node_index: u128, root_hash: H256) -> Result<Proof, &'static str> {
+        let n_nodes = self.get_n_nodes_at(&root_hash).ok_or("Tree state not found!")?;
+        if node_index >= n_nodes {
+            return Err("Node not found in specified tree state!");
+        }
+        Ok(self.compute_proof(node_index, n_nodes, self.load_right_edge(&root_hash, n_nodes)))
+    }
+
+    // Prove that `value` has index of `node_index` and that it exists inside `root_hash` state
+    // Gives the same result as `verify_proof` of the module synced with this client
+    pub fn verify_proof(self: &Self, proof: &[Option<H256>], value: L, node_index: u128, root_hash: H256) -> Result<(), &'static str> {
+        self.verify_proof_for_hash(proof, BlakeTwo256::hash_of(&value), node_index, root_hash)
+    }
+
+    // Prove that leaf `value_hash` has index of `node_index` and that it exists inside `root_hash` state
+    // Gives the same result as `verify_proof_for_hash` of the module synced with this client
+    pub fn verify_proof_for_hash(self: &Self, proof: &[Option<H256>], value_hash: H256, node_index: u128, root_hash: H256) -> Result<(), &'static str> {
+        merkle_proof::verify_proof::<ClientHasher>(proof, value_hash, node_index, self.n_nodes, root_hash)
+            .map_err(|e| e.as_str())
+    }
+
+    // Get number of leaf nodes in the tree state with root of `root_hash`
+    pub fn get_n_nodes_at(self: &Self, root_hash: &H256) -> Option<u128> {
+        self.root_sizes.get(root_hash).cloned()
+    }
+
+    // Root hash of the tree state with `n_nodes` leaves, `None` if the tree never had that many leaves
+    pub fn get_root_at(self: &Self, n_nodes: u128) -> Option<H256> {
+        if n_nodes == 0 || n_nodes > self.n_nodes {
+            return None;
+        }
+        Some(self.root_at(n_nodes as usize))
+    }
+
+    // Check if block `block_hash` was applied and not rolled back since
+    pub fn has_block(self: &Self, block_hash: &H256) -> bool {
+        self.blocks.iter().any(|block| block.block_hash == *block_hash)
+    }
+
+    // Number of leaf nodes right after block `block_hash` was applied
+    pub fn get_n_nodes_at_block(self: &Self, block_hash: &H256) -> Option<u128> {
+        self.blocks.iter().rev().find(|block| block.block_hash == *block_hash).map(|block| block.n_nodes)
+    }
+
     // Get zero based index of a specified node
-    pub fn get_node_index(self: &Self, value: Vec<u8>) -> u128 {
+    pub fn get_node_index(self: &Self, value: L) -> u128 {
         let value_hash = BlakeTwo256::hash_of(&value);
         *self.node_indices.get(&value_hash).unwrap()
     }
 
-    // Recursively find all the nodes needed to make the root hash `node_hash`
-    // TODO: Change the `node_hash` to `root_hash`
-    fn find_node(self: &Self, tree: &Tree, mut proof: Vec<Option<H256>>, hash: H256, node_hash: H256) -> Option<Proof> {
-        match tree.get(&hash) {
-            Some(node) => match node.sibling {
-                Some(sibling) => {
-                    // Node has a sibling, push it to proof array
-                    proof.push(Some(sibling));
-
-                    // Proof is complete
-                    if node.parent == node_hash {
-                        return Some(proof);
-                    }
-
-                    // Going up the tree one level
-                    self.find_node(&tree, proof, node.parent, node_hash)
-                },
-                None => {
-                    // Node doesnt have a sibling, push None to proof array
-                    proof.push(None);
-
-                    // Going up the tree one level
-                    self.find_node(&tree, proof, node.parent, node_hash)
-                },
-            },
-            None => None,
+    // Approximate number of bytes the tree takes in memory
+    pub fn memory_footprint(self: &Self) -> usize {
+        let nodes = self.leaves.capacity() + self.levels.iter().map(|level| level.capacity()).sum::<usize>();
+        // Every hash map entry also has one control byte
+        let index_entry = mem::size_of::<(H256, u128)>() + 1;
+        nodes * mem::size_of::<H256>()
+            + (self.node_indices.capacity() + self.root_sizes.capacity()) * index_entry
+            + self.edge_nodes.capacity() * mem::size_of::<Option<H256>>()
+    }
+
+    // Saves all the leaves in file `checkpoint` inside the snapshot folder
+    // Leaves are enough to continue syncing, since they determine every state of the tree
+    pub fn save_checkpoint(self: &Self) -> Result<(), &'static str> {
+        let name = match self.snapshot_dir {
+            Some(ref dir) => format!("{}/checkpoint", dir),
+            None => return Err("Snapshots are disabled!"),
+        };
+        let data = serde_json::to_string(&self.leaves).map_err(|_e| "Could not serialize leaves!")?;
+        let mut file = File::create(name).map_err(|_e| "Could not create file!")?;
+        file.write_all(data.as_bytes()).map_err(|_e| "Failed to write to file!")?;
+        file.sync_all().map_err(|_e| "Failed to sync file!")
+    }
+
+    // Loads the leaves saved by the last checkpoint
+    fn load_checkpoint(self: &Self) -> Result<Vec<H256>, &'static str> {
+        let name = match self.snapshot_dir {
+            Some(ref dir) => format!("{}/checkpoint", dir),
+            None => return Err("Snapshots are disabled!"),
+        };
+        let mut content = String::new();
+        let mut file = File::open(name).map_err(|_e| "Could not read file!")?;
+        file.read_to_string(&mut content).map_err(|_e| "Could not read file!")?;
+        serde_json::from_str(&content).map_err(|_e| "Could not parse checkpoint json!")
+    }
+
+    // Saves the right edge of the current tree state in the snapshot folder, named by its root
+    fn save_snapshot(self: &Self, root_hash: &H256) {
+        let name = match self.snapshot_dir {
+            Some(ref dir) if self.cache_snapshots => format!("{}/{:?}", dir, root_hash),
+            _ => return,
+        };
+        let snapshot = Snapshot {
+            n_nodes: self.n_nodes,
+            right_edge: (0..self.levels.len() + 1).map(|level| *self.level(level).last().expect("Levels are not empty; qed")).collect(),
+        };
+        // Snapshots are only a cache, a state without one is hashed from the leaves
+        if let Ok(data) = serde_json::to_string(&snapshot) {
+            let _ = File::create(name).and_then(|mut file| file.write_all(data.as_bytes()));
+        }
+    }
+
+    // Right edge of the earlier tree state `root_hash` with `n_nodes` leaves, if its snapshot was saved
+    fn load_right_edge(self: &Self, root_hash: &H256, n_nodes: u128) -> Option<Vec<H256>> {
+        // Right edge of the current state is the end of every level
+        if n_nodes == self.n_nodes || !self.cache_snapshots {
+            return None;
+        }
+        let name = format!("{}/{:?}", self.snapshot_dir.as_ref()?, root_hash);
+        let mut content = String::new();
+        File::open(name).ok()?.read_to_string(&mut content).ok()?;
+        let snapshot: Snapshot = serde_json::from_str(&content).ok()?;
+        if snapshot.n_nodes != n_nodes {
+            return None;
         }
+        Some(snapshot.right_edge)
+    }
+
+    // Removes the saved tree state `root_hash`, if there is one
+    fn remove_snapshot(self: &Self, root_hash: &H256) {
+        if let Some(ref dir) = self.snapshot_dir {
+            let _ = fs::remove_file(format!("{}/{:?}", dir, root_hash));
+        }
+    }
+
+    // Compute proof for node at `node_index` in the tree made of the first `n_nodes` leaves
+    // Last nodes of the levels of that state are taken from `right_edge` when its snapshot was loaded
+    fn compute_proof(self: &Self, node_index: u128, n_nodes: u128, right_edge: Option<Vec<H256>>) -> Proof {
+        let n_nodes = n_nodes as usize;
+        let mut proof: Proof = vec![];
+        let mut index = node_index as usize;
+        let mut level = 0;
+        // Going up the tree one level at a time until only the root is left
+        while Self::level_width(n_nodes, level) > 1 {
+            // Last node on the level doesn't have a sibling if the level has odd number of nodes
+            proof.push(self.node_at(level, index ^ 1, n_nodes, right_edge.as_ref().map(|edge| &edge[..])));
+            index >>= 1;
+            level += 1;
+        }
+        proof
+    }
+
+    // Hash of the node at `index` on `level` of the tree made of the first `n_nodes` leaves
+    fn node_at(self: &Self, level: usize, index: usize, n_nodes: usize, right_edge: Option<&[H256]>) -> Option<H256> {
+        if index >= Self::level_width(n_nodes, level) {
+            return None;
+        }
+        // Nodes of the current state, and nodes whose leaves were all inserted before `n_nodes`, are saved
+        if n_nodes == self.leaves.len() || (index + 1) << level <= n_nodes {
+            return Some(self.level(level)[index]);
+        }
+        // Node on the right edge of a previous state, taken from its snapshot or hashed again from its children in that state
+        if let Some(node) = right_edge.and_then(|edge| edge.get(level)) {
+            return Some(*node);
+        }
+        let left = self.node_at(level - 1, index * 2, n_nodes, right_edge)?;
+        Some(match self.node_at(level - 1, index * 2 + 1, n_nodes, right_edge) {
+            Some(right) => BlakeTwo256::hash_of(&[left, right]),
+            None => BlakeTwo256::hash_of(&left),
+        })
+    }
+
+    // Root hash of the tree made of the first `n_nodes` leaves
+    fn root_at(self: &Self, n_nodes: usize) -> H256 {
+        let mut level = 0;
+        while Self::level_width(n_nodes, level) > 1 {
+            level += 1;
+        }
+        self.node_at(level, 0, n_nodes, None).expect("Tree with at least one leaf has a root; qed")
+    }
+
+    // Hashes of all nodes on `level`, where leaves are on level 0
+    fn level(self: &Self, level: usize) -> &Vec<H256> {
+        if level == 0 {
+            &self.leaves
+        } else {
+            &self.levels[level - 1]
+        }
+    }
+
+    // Number of nodes on `level` of the tree with `n_nodes` leaves
+    fn level_width(n_nodes: usize, level: usize) -> usize {
+        (n_nodes + (1 << level) - 1) >> level
+    }
+
+    // Hash of the parent at `parent_index` made from its current children on `level`
+    fn hash_children(self: &Self, level: usize, parent_index: usize) -> H256 {
+        let nodes = self.level(level);
+        match nodes.get(parent_index * 2 + 1) {
+            // Node has a sibling, make a hash of the pair
+            Some(right) => BlakeTwo256::hash_of(&[nodes[parent_index * 2], *right]),
+            // Node doesnt have a sibling, just do the hash of itself
+            None => BlakeTwo256::hash_of(&nodes[parent_index * 2]),
+        }
+    }
+
+    // Edge nodes of the current state
+    // Level has an edge node only if bit on that level of `n_nodes` is set, and it is the last complete node on it
+    fn current_edge_nodes(self: &Self) -> EdgeNodes {
+        let n_nodes = self.leaves.len();
+        let mut edge_nodes = vec![];
+        let mut level = 0;
+        while n_nodes >> level > 0 {
+            let width = n_nodes >> level;
+            edge_nodes.push(if width & 1 == 1 { Some(self.level(level)[width - 1]) } else { None });
+            level += 1;
+        }
+        edge_nodes
     }
 
     // Insert a hash into the tree
     fn insert(self: &mut Self, value_hash: H256) {
-        let mut pair_hash = value_hash;
-        let mut new_edge = value_hash;
         // Get the level where the next edge node is
-        let next_edge_addition_level = self.count_bit_set_from_right(self.n_nodes);
-
-        // Loop throught the edge nodes and save all connections between nodes
-        for i in 0..self.edge_nodes.len() {
-            let edge_node = self.edge_nodes[i];
-            pair_hash = match edge_node {
-                Some(hash) => {
-                    // If edge node on `i` level is not None, make a hash of pair [hash of the value, edge node]
-                    let new_hash = BlakeTwo256::hash_of(&[hash, pair_hash]);
-                    // Edge node now has a sibling and a parent
-                    self.tree.insert(hash, Node {
-                        parent: new_hash,
-                        sibling: Some(pair_hash)
-                    });
-                    // New node now has a sibling (edge node) and a parent
-                    self.tree.insert(pair_hash, Node {
-                        parent: new_hash,
-                        sibling: Some(hash)
-                    });
-                    new_hash
-                },
-                None => {
-                    // No edge node on this level, just do the hash of itself
-                    let new_hash = BlakeTwo256::hash_of(&pair_hash);
-                    // New node doesnt have a sibling, but it has the parent
-                    self.tree.insert(pair_hash, Node {
-                        parent: new_hash,
-                        sibling: None
-                    });
-                    new_hash
-                }
-            };
-            // Save new edge node
-            if (i + 1) as u8 == next_edge_addition_level {
-                new_edge = pair_hash;
+        let next_edge_addition_level = self.count_bit_set_from_right(self.n_nodes) as usize;
+        let node_index = self.leaves.len();
+        self.leaves.push(value_hash);
+
+        // Only the ancestors of the new leaf change, hash them again level by level
+        let mut index = node_index;
+        let mut level = 0;
+        while self.level(level).len() > 1 {
+            let parent_index = index >> 1;
+            let parent_hash = self.hash_children(level, parent_index);
+            if level == self.levels.len() {
+                self.levels.push(vec![]);
+            }
+            let parents = &mut self.levels[level];
+            if parent_index == parents.len() {
+                parents.push(parent_hash);
+            } else {
+                parents[parent_index] = parent_hash;
             }
+            index = parent_index;
+            level += 1;
         }
+        let root_hash = self.level(level)[0];
+        // Complete subtree ending with the new leaf is the new edge
+        let new_edge = self.level(next_edge_addition_level)[node_index >> next_edge_addition_level];
 
         // Update root hash
-        self.root_hash = Some(pair_hash);
+        self.root_hash = Some(root_hash);
         // Set node index before we increment n_nodes
         self.node_indices.insert(value_hash, self.n_nodes);
         // Increment n_nodes
         self.n_nodes += 1;
+        self.root_sizes.insert(root_hash, self.n_nodes);
+        self.save_snapshot(&root_hash);
         // Save new edge, and remove the invalid ones
-        self.update_edges(new_edge, next_edge_addition_level as usize);
-
-        // If tree has one level, no need to save snapshot
-        if value_hash != pair_hash {
-            self.save_snapshot(&pair_hash, &self.tree);
-        }
-    }
-
-    // Saves the current tree state in `snapshots` folder
-    // Snapshots will be used to check if some values exists inside `new_root_hash`
-
-    // TODO: Since we are storing tree states to cold storage,
-    // add functionality to continue syncing with on-chain tree from last saved checkpoint
-    // Information about last saved checkpoint will be saved in file checkpoint inside `snapshots` folder
-    fn save_snapshot(self: &Self, new_root_hash: &H256, tree: &Tree) {
-        let name = format!("src/snapshots/{:?}", new_root_hash);
-        let content = serde_json::to_string_pretty(tree);
-        match content {
-            Ok(data) => {
-                let mut file = File::create(name).expect("Could not create file!");
-                file.write_all(data.as_bytes()).expect("Failed to write to file!");
-                file.sync_all().expect("Failed to sync file!");
-            },
-            Err(e) => panic!(e),
-        }
-    }
-
-    // Loads the tree with state `root_hash`
-    fn load_snapshot(self: &Self, root_hash: &H256) -> Result<Tree, &'static str> {
-        let name = format!("src/snapshots/{:?}", root_hash);
-        let file = File::open(name);
-        let mut content = String::new();
-        match file {
-            Ok(mut f) => {
-                let read = f.read_to_string(&mut content);
-                match read {
-                    Ok(_a) => {
-                        let tree: Tree = serde_json::from_str(&content).unwrap();
-                        Ok(tree)
-                    },
-                    Err(_e) => Err("Could not parse tree json!"),
-                }
-            },
-            Err(_e) => Err("Could not read file!"),
-        }
+        self.update_edges(new_edge, next_edge_addition_level);
     }
 
     fn update_edges(self: &mut Self, new_edge_value: H256, addition_at_level: usize) {
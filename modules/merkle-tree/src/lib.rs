@@ -1,6 +1,8 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
 #[cfg(feature = "std")]
 extern crate serde;
-
+#[cfg(feature = "std")]
 extern crate serde_derive;
 
 #[cfg(feature = "std")]
@@ -12,14 +14,30 @@ extern crate sr_std as rstd;
 extern crate srml_support as runtime_support;
 extern crate substrate_primitives as primitives;
 extern crate srml_system as system;
+extern crate srml_balances as balances;
+extern crate merkle_proof;
 
 #[macro_use]
 extern crate parity_codec_derive;
 
+// Client side trees are only used outside of the runtime
+#[cfg(feature = "std")]
 pub mod client;
 pub mod merkle_tree;
-pub use crate::merkle_tree::{Event, Module, RawEvent, Trait};
-pub use crate::client::MerkleTreeClient;
+#[cfg(feature = "std")]
+pub mod witness;
+pub use crate::merkle_tree::{root_from_header, Call, Event, FeeDestination, Log, Module, RawEvent, RawLog, RootDigestItem, Trait, TreeHasher};
+// Genesis config of the fees, only built outside of the runtime
+#[cfg(feature = "std")]
+pub use crate::merkle_tree::GenesisConfig;
+#[cfg(feature = "std")]
+pub use crate::client::{ClientHasher, InsertEvent, MerkleTreeClient, RollbackTarget, SyncDivergence};
+#[cfg(feature = "std")]
+pub use crate::witness::WitnessTracker;
+
+#[cfg(test)]
+#[macro_use]
+mod mock;
 
 #[cfg(test)]
 mod tests {
@@ -29,62 +47,97 @@ mod tests {
 	use substrate_primitives::{H256, Blake2Hasher};
 
 	use runtime_primitives::{
-		testing::{Digest, DigestItem, Header},
-		traits::{BlakeTwo256, Hash},
+		testing::DigestItem,
+		traits::{BlakeTwo256, Digest as DigestT, Hash, Header as HeaderT, OnFinalise, OnInitialise},
 		BuildStorage,
 	};
 	use system::{EventRecord, Phase};
 
-	impl_outer_origin! {
-		pub enum Origin for Test {}
-	}
-
-	impl_outer_event! {
-		pub enum Event for Test {
-			merkle_tree<T>,
+	// Mock runtimes keep the log in the generic digest item encoded as `Other`
+	impl RootDigestItem<H256> for DigestItem {
+		fn as_root_change(&self) -> Option<(H256, u128)> {
+			match *self {
+				runtime_primitives::generic::DigestItem::Other(ref data) => codec::Decode::decode(&mut &data[..])
+					.and_then(|log: RawLog<H256>| log.as_root_change()),
+				_ => None,
+			}
 		}
 	}
 
+	mock_runtime!(Test, Vec<u8>, aggregate: false, store: true, unique: false);
+
 	impl_outer_dispatch! {
 		pub enum Call for Test where origin: Origin {}
 	}
 
-	#[derive(Clone, Eq, PartialEq)]
-	pub struct Test;
+	// Same runtime with leaves made from typed records
+	mod typed {
+		use super::*;
+
+		#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+		pub struct Record {
+			pub account: u64,
+			pub amount: u128,
+		}
+
+		mock_runtime!(TypedTest, Record, aggregate: false, store: false, unique: false);
+	}
+
+	// Same runtime with inserts aggregated per block
+	mod aggregated {
+		use super::*;
 
-	impl system::Trait for Test {
-		type Origin = Origin;
-		type Index = u64;
-		type BlockNumber = u64;
-		type Hash = H256;
-		type Hashing = BlakeTwo256;
-		type Digest = Digest;
-		type AccountId = u64;
-		type Header = Header;
-		type Event = Event;
-		type Log = DigestItem;
+		mock_runtime!(AggregatedTest, Vec<u8>, aggregate: true, store: false, unique: false);
 	}
-	impl Trait for Test {
-		type Event = Event;
+
+	// Same runtime with unique leaves, aggregated so duplicates queued in the same block are covered
+	mod unique {
+		use super::*;
+
+		mock_runtime!(UniqueTest, Vec<u8>, aggregate: true, store: false, unique: true);
 	}
 
-	type System = system::Module<Test>;
-	type MerkleTree = Module<Test>;
+	// Leaf fee of 10 and byte fee of 1. Account 3 can't pay for an insert, account 2 is the treasury
+	fn new_test_ext_with_fees(fee_recipient: FeeDestination<u64>) -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(balances::GenesisConfig::<Test> {
+			balances: vec![(1, 100), (2, 100), (3, 10)],
+			transaction_base_fee: 0,
+			transaction_byte_fee: 0,
+			existential_deposit: 0,
+			transfer_fee: 0,
+			creation_fee: 0,
+			reclaim_rebate: 0,
+		}.build_storage().unwrap().0);
+		t.extend(GenesisConfig::<Test> {
+			leaf_fee: 10,
+			byte_fee: 1,
+			fee_recipient,
+			..Default::default()
+		}.build_storage().unwrap().0);
+		t.into()
+	}
 
-	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
-		system::GenesisConfig::<Test>::default().build_storage().unwrap().0.into()
+	// 3 inserts per block and 2 inserts per account in eras of `insert_era_length` blocks
+	fn new_test_ext_with_limits(insert_era_length: u64) -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(GenesisConfig::<Test> {
+			max_inserts_per_block: 3,
+			max_inserts_per_account_per_era: 2,
+			insert_era_length,
+			..Default::default()
+		}.build_storage().unwrap().0);
+		t.into()
 	}
 
-	fn get_event_values() -> Vec<H256> {
-		let mut event_values: Vec<H256> = vec![];
-		for event_record in System::events().iter() {
-			let hash: H256 = match event_record.event {
-				Event::merkle_tree(RawEvent::Insert(e)) => e,
-				_ => panic!("hello there")
-			};
-			event_values.push(hash);
-		}
-		event_values
+	// Events of inserting `leaves` on top of the tree made of `previous` leaves
+	fn insert_events(previous: &[H256], leaves: &[H256]) -> Vec<InsertEvent> {
+		let mut tree = MerkleTreeClient::with_snapshot_dir(None);
+		tree.append_leaves(previous.to_vec());
+		leaves.iter().map(|leaf| {
+			tree.append_leaves(vec![*leaf]);
+			(tree.n_nodes - 1, *leaf, tree.root_hash.unwrap())
+		}).collect()
 	}
 
 	#[test]
@@ -106,7 +159,7 @@ mod tests {
 		    let a = "a".to_string().as_bytes().to_vec();
 		    let a_hash = BlakeTwo256::hash_of(&a);
 
-		    let mut result = MerkleTree::insert(a);
+		    let mut result = MerkleTree::insert(Origin::signed(1), a);
 			assert_eq!(result, Ok(()));
 
 		    let mut root_hash = MerkleTree::root_hash();
@@ -121,7 +174,7 @@ mod tests {
 
 		    let ab_hash = BlakeTwo256::hash_of(&[a_hash, b_hash]);
 
-		    result = MerkleTree::insert(b);
+		    result = MerkleTree::insert(Origin::signed(1), b);
 			assert_eq!(result, Ok(()));
 
 		    root_hash = MerkleTree::root_hash();
@@ -138,7 +191,7 @@ mod tests {
 
 		    let abc1_hash = BlakeTwo256::hash_of(&[ab_hash, c1_hash]);
 
-		    result = MerkleTree::insert(c);
+		    result = MerkleTree::insert(Origin::signed(1), c);
 			assert_eq!(result, Ok(()));
 
 		    root_hash = MerkleTree::root_hash();
@@ -154,7 +207,7 @@ mod tests {
 		    let cd_hash = BlakeTwo256::hash_of(&[c_hash, d_hash]);
 		    let abcd_hash = BlakeTwo256::hash_of(&[ab_hash, cd_hash]);
 
-		    result = MerkleTree::insert(d);
+		    result = MerkleTree::insert(Origin::signed(1), d);
 			assert_eq!(result, Ok(()));
 
 			root_hash = MerkleTree::root_hash();
@@ -171,7 +224,7 @@ mod tests {
 		    let e2_hash = BlakeTwo256::hash_of(&e1_hash);
 		    let abcde2_hash = BlakeTwo256::hash_of(&[abcd_hash, e2_hash]);
 
-		    result = MerkleTree::insert(e);
+		    result = MerkleTree::insert(Origin::signed(1), e);
 			assert_eq!(result, Ok(()));
 
 			root_hash = MerkleTree::root_hash();
@@ -188,7 +241,7 @@ mod tests {
 		    let ef1_hash = BlakeTwo256::hash_of(&ef_hash);
 		    let abcdef1_hash = BlakeTwo256::hash_of(&[abcd_hash, ef1_hash]);
 
-		    result = MerkleTree::insert(f);
+		    result = MerkleTree::insert(Origin::signed(1), f);
 			assert_eq!(result, Ok(()));
 
 			root_hash = MerkleTree::root_hash();
@@ -205,7 +258,7 @@ mod tests {
 		    let efg1_hash = BlakeTwo256::hash_of(&[ef_hash, g1_hash]);
 		    let abcdefg1_hash = BlakeTwo256::hash_of(&[abcd_hash, efg1_hash]);
 
-		    result = MerkleTree::insert(g);
+		    result = MerkleTree::insert(Origin::signed(1), g);
 			assert_eq!(result, Ok(()));
 
 			root_hash = MerkleTree::root_hash();
@@ -222,7 +275,7 @@ mod tests {
 		    let efgh_hash = BlakeTwo256::hash_of(&[ef_hash, gh_hash]);
 		    let abcdefgh_hash = BlakeTwo256::hash_of(&[abcd_hash, efgh_hash]);
 
-		    result = MerkleTree::insert(h);
+		    result = MerkleTree::insert(Origin::signed(1), h);
 			assert_eq!(result, Ok(()));
 
 			root_hash = MerkleTree::root_hash();
@@ -240,7 +293,7 @@ mod tests {
 		    let i3_hash = BlakeTwo256::hash_of(&i2_hash);
 		    let abcdefghi3_hash = BlakeTwo256::hash_of(&[abcdefgh_hash, i3_hash]);
 
-		    result = MerkleTree::insert(i);
+		    result = MerkleTree::insert(Origin::signed(1), i);
 			assert_eq!(result, Ok(()));
 
 			root_hash = MerkleTree::root_hash();
@@ -257,14 +310,15 @@ mod tests {
 			let a = "a".to_string().as_bytes().to_vec();
 		    let a_hash = BlakeTwo256::hash_of(&a);
 
-			let result = MerkleTree::insert(a);
+			let result = MerkleTree::insert(Origin::signed(1), a);
 			assert_eq!(result, Ok(()));
 			assert_eq!(
 				System::events(),
 				vec![
 					EventRecord {
 						phase: Phase::ApplyExtrinsic(0),
-						event: RawEvent::Insert(a_hash).into()
+						// Only leaf of the tree is also its root
+						event: RawEvent::Insert(0, a_hash, a_hash).into()
 					}
 				]
 			);
@@ -278,18 +332,18 @@ mod tests {
 			let b = "b".to_string().as_bytes().to_vec();
 			let c = "c".to_string().as_bytes().to_vec();
 
-			let mut result = MerkleTree::insert(a);
+			let mut result = MerkleTree::insert(Origin::signed(1), a);
 			assert_eq!(result, Ok(()));
-			result = MerkleTree::insert(b);
+			result = MerkleTree::insert(Origin::signed(1), b);
 			assert_eq!(result, Ok(()));
-			result = MerkleTree::insert(c);
+			result = MerkleTree::insert(Origin::signed(1), c);
 			assert_eq!(result, Ok(()));
 
 			let root_hash = MerkleTree::root_hash();
 
-			let mut client_tree = MerkleTreeClient::new();
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
 			let event_values = get_event_values();
-			client_tree.build_tree_from_events(event_values);
+			assert_eq!(client_tree.build_tree_from_events(event_values), Ok(()));
 
 			let client_root_hash = client_tree.root_hash;
 			assert_eq!(client_root_hash, root_hash);
@@ -303,18 +357,18 @@ mod tests {
 			let b = "b".to_string().as_bytes().to_vec();
 			let c = "c".to_string().as_bytes().to_vec();
 
-			let mut result = MerkleTree::insert(a.clone());
+			let mut result = MerkleTree::insert(Origin::signed(1), a.clone());
 			assert_eq!(result, Ok(()));
-			result = MerkleTree::insert(b.clone());
+			result = MerkleTree::insert(Origin::signed(1), b.clone());
 			assert_eq!(result, Ok(()));
-			result = MerkleTree::insert(c.clone());
+			result = MerkleTree::insert(Origin::signed(1), c.clone());
 			assert_eq!(result, Ok(()));
 
 			let root_hash = MerkleTree::root_hash();
 
-			let mut client_tree = MerkleTreeClient::new();
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
 			let event_values = get_event_values();
-			client_tree.build_tree_from_events(event_values);
+			assert_eq!(client_tree.build_tree_from_events(event_values), Ok(()));
 
 			let node_index = client_tree.get_node_index(a.clone());
 			let proof = client_tree.get_proof_for(a.clone(), root_hash.unwrap());
@@ -331,26 +385,26 @@ mod tests {
 			let c = "c".to_string().as_bytes().to_vec();
 			let d = "d".to_string().as_bytes().to_vec();
 
-			let mut result = MerkleTree::insert(a.clone());
+			let mut result = MerkleTree::insert(Origin::signed(1), a.clone());
 			assert_eq!(result, Ok(()));
-			result = MerkleTree::insert(b.clone());
+			result = MerkleTree::insert(Origin::signed(1), b.clone());
 			assert_eq!(result, Ok(()));
 
 			let root_hash_after_b = MerkleTree::root_hash();
 
-			result = MerkleTree::insert(c.clone());
+			result = MerkleTree::insert(Origin::signed(1), c.clone());
 			assert_eq!(result, Ok(()));
 
 			let root_hash_after_c = MerkleTree::root_hash();
 
-			result = MerkleTree::insert(d.clone());
+			result = MerkleTree::insert(Origin::signed(1), d.clone());
 			assert_eq!(result, Ok(()));
 
 			let root_hash_after_d = MerkleTree::root_hash();
 
-			let mut client_tree = MerkleTreeClient::new();
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
 			let event_values = get_event_values();
-			client_tree.build_tree_from_events(event_values);
+			assert_eq!(client_tree.build_tree_from_events(event_values), Ok(()));
 
 			let mut node_index = client_tree.get_node_index(a.clone());
 			let mut proof = client_tree.get_proof_for(a.clone(), root_hash_after_b.unwrap());
@@ -404,14 +458,14 @@ mod tests {
 			let b = "b".to_string().as_bytes().to_vec();
 			let c = "c".to_string().as_bytes().to_vec();
 
-			let result = MerkleTree::insert(a.clone());
+			let result = MerkleTree::insert(Origin::signed(1), a.clone());
 			assert_eq!(result, Ok(()));
 
 			let root_hash = MerkleTree::root_hash();
 
-			let mut client_tree = MerkleTreeClient::new();
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
 			let event_values = get_event_values();
-			client_tree.build_tree_from_events(event_values);
+			assert_eq!(client_tree.build_tree_from_events(event_values), Ok(()));
 
 			let mut proof = client_tree.get_proof_for(b.clone(), root_hash.unwrap());
 			assert_eq!(proof, Err("Node not found in specified tree state!"));
@@ -419,4 +473,865 @@ mod tests {
 			assert_eq!(proof, Err("Node not found in specified tree state!"));
 		});
 	}
+
+	#[test]
+	fn should_be_able_to_create_proof_for_previous_states_without_snapshots() {
+		with_externalities(&mut new_test_ext(), || {
+			let a = "a".to_string().as_bytes().to_vec();
+			let b = "b".to_string().as_bytes().to_vec();
+			let c = "c".to_string().as_bytes().to_vec();
+			let d = "d".to_string().as_bytes().to_vec();
+			let e = "e".to_string().as_bytes().to_vec();
+
+			let mut roots = vec![];
+			for value in vec![a.clone(), b.clone(), c.clone(), d.clone(), e.clone()] {
+				let result = MerkleTree::insert(Origin::signed(1), value);
+				assert_eq!(result, Ok(()));
+				roots.push(MerkleTree::root_hash().unwrap());
+			}
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(get_event_values()), Ok(()));
+
+			let values = vec![a, b, c, d, e];
+			for (n_nodes, root_hash) in roots.iter().enumerate() {
+				assert_eq!(client_tree.get_n_nodes_at(root_hash), Some(n_nodes as u128 + 1));
+				// Every value inserted up to this state has a valid proof
+				for value in values[..n_nodes + 1].iter() {
+					let node_index = client_tree.get_node_index(value.clone());
+					let proof = client_tree.get_proof_for(value.clone(), *root_hash);
+					let res = MerkleTree::verify_proof(proof.unwrap(), value.clone(), node_index, *root_hash);
+					assert_eq!(res, Ok(()));
+				}
+				// Values inserted later are not part of this state
+				for value in values[n_nodes + 1..].iter() {
+					let proof = client_tree.get_proof_for(value.clone(), *root_hash);
+					assert_eq!(proof, Err("Node not found in specified tree state!"));
+				}
+			}
+		});
+	}
+
+	#[test]
+	fn should_be_able_to_create_proof_by_index() {
+		with_externalities(&mut new_test_ext(), || {
+			let values: Vec<Vec<u8>> = "abcdefghijk".chars().map(|c| c.to_string().as_bytes().to_vec()).collect();
+
+			let mut roots = vec![];
+			for value in values.iter() {
+				let result = MerkleTree::insert(Origin::signed(1), value.clone());
+				assert_eq!(result, Ok(()));
+				roots.push(MerkleTree::root_hash().unwrap());
+			}
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(get_event_values()), Ok(()));
+
+			for (n_nodes, root_hash) in roots.iter().enumerate() {
+				for node_index in 0..(n_nodes as u128 + 1) {
+					let value = values[node_index as usize].clone();
+					let proof = client_tree.get_proof_for_index(node_index, *root_hash);
+					assert_eq!(proof, client_tree.get_proof_for(value.clone(), *root_hash));
+					let res = MerkleTree::verify_proof(proof.unwrap(), value, node_index, *root_hash);
+					assert_eq!(res, Ok(()));
+				}
+				let proof = client_tree.get_proof_for_index(n_nodes as u128 + 1, *root_hash);
+				assert_eq!(proof, Err("Node not found in specified tree state!"));
+			}
+		});
+	}
+
+	#[test]
+	fn should_cache_snapshots_of_previous_states() {
+		with_externalities(&mut new_test_ext(), || {
+			let values: Vec<Vec<u8>> = "abcdefg".chars().map(|c| c.to_string().as_bytes().to_vec()).collect();
+
+			let mut roots = vec![];
+			for value in values.iter() {
+				let result = MerkleTree::insert(Origin::signed(1), value.clone());
+				assert_eq!(result, Ok(()));
+				roots.push(MerkleTree::root_hash().unwrap());
+			}
+
+			let snapshot_dir = std::env::temp_dir().join("merkle_tree_snapshot_test");
+			std::fs::create_dir_all(&snapshot_dir).unwrap();
+			let mut cached_tree = MerkleTreeClient::new(snapshot_dir.to_str().unwrap().to_string());
+			assert_eq!(cached_tree.build_tree_from_events(get_event_values()), Ok(()));
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(get_event_values()), Ok(()));
+
+			// Proofs made with the snapshots are the same as the ones hashed from the leaves
+			for (n_nodes, root_hash) in roots.iter().enumerate() {
+				assert!(snapshot_dir.join(format!("{:?}", root_hash)).exists());
+				for node_index in 0..(n_nodes as u128 + 1) {
+					let proof = cached_tree.get_proof_for_index(node_index, *root_hash);
+					assert_eq!(proof, client_tree.get_proof_for_index(node_index, *root_hash));
+					let res = MerkleTree::verify_proof(proof.unwrap(), values[node_index as usize].clone(), node_index, *root_hash);
+					assert_eq!(res, Ok(()));
+				}
+			}
+
+			// Snapshots of the states that were rolled back are removed
+			assert_eq!(cached_tree.rollback_to(RollbackTarget::NNodes(5)), Ok(()));
+			assert!(snapshot_dir.join(format!("{:?}", roots[4])).exists());
+			assert!(!snapshot_dir.join(format!("{:?}", roots[5])).exists());
+			assert!(!snapshot_dir.join(format!("{:?}", roots[6])).exists());
+		});
+	}
+
+	#[test]
+	fn should_be_able_to_continue_syncing_from_checkpoint() {
+		with_externalities(&mut new_test_ext(), || {
+			let a = "a".to_string().as_bytes().to_vec();
+			let b = "b".to_string().as_bytes().to_vec();
+			let c = "c".to_string().as_bytes().to_vec();
+
+			for value in vec![a, b, c.clone()] {
+				let result = MerkleTree::insert(Origin::signed(1), value);
+				assert_eq!(result, Ok(()));
+			}
+			let root_hash = MerkleTree::root_hash();
+
+			let snapshot_dir = std::env::temp_dir().join("merkle_tree_checkpoint_test");
+			std::fs::create_dir_all(&snapshot_dir).unwrap();
+			let snapshot_dir = snapshot_dir.to_str().unwrap().to_string();
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(Some(snapshot_dir.clone()));
+			assert_eq!(client_tree.build_tree_from_events(get_event_values()), Ok(()));
+			assert_eq!(client_tree.save_checkpoint(), Ok(()));
+
+			let restored_tree = MerkleTreeClient::from_checkpoint(snapshot_dir).unwrap();
+			assert_eq!(restored_tree.root_hash, root_hash);
+			assert_eq!(restored_tree.edge_nodes, client_tree.edge_nodes);
+			assert_eq!(restored_tree.node_indices, client_tree.node_indices);
+
+			let node_index = restored_tree.get_node_index(c.clone());
+			let proof = restored_tree.get_proof_for(c.clone(), root_hash.unwrap());
+			let res = MerkleTree::verify_proof(proof.unwrap(), c.clone(), node_index, root_hash.unwrap());
+			assert_eq!(res, Ok(()));
+
+			let client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.save_checkpoint(), Err("Snapshots are disabled!"));
+		});
+	}
+
+	#[test]
+	fn should_build_same_tree_from_leaves_in_bulk() {
+		with_externalities(&mut new_test_ext(), || {
+			let values: Vec<Vec<u8>> = "abcdefghijklm".chars().map(|c| c.to_string().as_bytes().to_vec()).collect();
+
+			let mut roots = vec![];
+			for value in values.iter() {
+				let result = MerkleTree::insert(Origin::signed(1), value.clone());
+				assert_eq!(result, Ok(()));
+				roots.push(MerkleTree::root_hash().unwrap());
+			}
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(get_event_values()), Ok(()));
+
+			let snapshot_dir = std::env::temp_dir().join("merkle_tree_bulk_test");
+			std::fs::create_dir_all(&snapshot_dir).unwrap();
+			let snapshot_dir = snapshot_dir.to_str().unwrap().to_string();
+
+			let bulk_tree = MerkleTreeClient::from_leaves(get_event_values().into_iter().map(|(_, leaf, _)| leaf), Some(snapshot_dir.clone())).unwrap();
+			assert_eq!(bulk_tree.root_hash, MerkleTree::root_hash());
+			assert_eq!(bulk_tree.edge_nodes, MerkleTree::edge_nodes());
+			assert_eq!(bulk_tree.root_hash, client_tree.root_hash);
+			assert_eq!(bulk_tree.edge_nodes, client_tree.edge_nodes);
+			assert_eq!(bulk_tree.node_indices, client_tree.node_indices);
+
+			// Proofs for previous states are available as well
+			for (n_nodes, root_hash) in roots.iter().enumerate() {
+				assert_eq!(bulk_tree.get_n_nodes_at(root_hash), Some(n_nodes as u128 + 1));
+				let proof = bulk_tree.get_proof_for(values[0].clone(), *root_hash);
+				let res = MerkleTree::verify_proof(proof.unwrap(), values[0].clone(), 0, *root_hash);
+				assert_eq!(res, Ok(()));
+			}
+
+			// Checkpoint is saved once the tree is built
+			let restored_tree = MerkleTreeClient::from_checkpoint(snapshot_dir).unwrap();
+			assert_eq!(restored_tree.root_hash, bulk_tree.root_hash);
+
+			let empty_tree = MerkleTreeClient::from_leaves(vec![], None).unwrap();
+			assert_eq!(empty_tree.root_hash, None);
+			assert_eq!(empty_tree.edge_nodes, vec![]);
+		});
+	}
+
+	#[test]
+	fn should_keep_proofs_of_watched_leaves_up_to_date() {
+		with_externalities(&mut new_test_ext(), || {
+			let values: Vec<Vec<u8>> = "abcdefghijklmnopqrstu".chars().map(|c| c.to_string().as_bytes().to_vec()).collect();
+			let watched_indices = vec![0u128, 2, 5, 8, 16];
+
+			let mut tracker = WitnessTracker::new();
+			for node_index in watched_indices.iter() {
+				assert_eq!(tracker.watch_index(*node_index), Ok(()));
+			}
+			tracker.watch_value(values[11].clone());
+
+			for (n_nodes, value) in values.iter().enumerate() {
+				let result = MerkleTree::insert(Origin::signed(1), value.clone());
+				assert_eq!(result, Ok(()));
+
+				let (_, new_leaf, _) = *get_event_values().last().unwrap();
+				tracker.build_tree_from_events(vec![new_leaf]);
+
+				let root_hash = MerkleTree::root_hash().unwrap();
+				assert_eq!(tracker.root_hash(), Some(root_hash));
+
+				// Every watched leaf that is inserted has a valid proof for the newest state
+				for node_index in watched_indices.iter().filter(|node_index| **node_index <= n_nodes as u128) {
+					let proof = tracker.get_proof_for_index(*node_index);
+					let res = MerkleTree::verify_proof(proof.unwrap(), values[*node_index as usize].clone(), *node_index, root_hash);
+					assert_eq!(res, Ok(()));
+				}
+				if n_nodes >= 11 {
+					let (node_index, proof) = tracker.get_proof_for(values[11].clone()).unwrap();
+					assert_eq!(node_index, 11);
+					let res = MerkleTree::verify_proof(proof, values[11].clone(), node_index, root_hash);
+					assert_eq!(res, Ok(()));
+				}
+			}
+
+			assert_eq!(tracker.watched_indices(), vec![0, 2, 5, 8, 11, 16]);
+			assert_eq!(tracker.get_proof_for_index(1), Err("Node is not watched!"));
+			assert_eq!(tracker.watch_index(3), Err("Leaf is already inserted!"));
+		});
+	}
+
+	#[test]
+	fn should_verify_proofs_the_same_way_everywhere() {
+		use merkle_proof::{Blake2Hasher, Hasher};
+
+		with_externalities(&mut new_test_ext(), || {
+			let values: Vec<Vec<u8>> = "abcdef".chars().map(|c| c.to_string().as_bytes().to_vec()).collect();
+			for value in values.iter() {
+				let result = MerkleTree::insert(Origin::signed(1), value.clone());
+				assert_eq!(result, Ok(()));
+			}
+			let root_hash = MerkleTree::root_hash().unwrap();
+			let n_nodes = MerkleTree::n_nodes();
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(get_event_values()), Ok(()));
+
+			for value in values.iter() {
+				let node_index = client_tree.get_node_index(value.clone());
+				let proof = client_tree.get_proof_for(value.clone(), root_hash).unwrap();
+
+				assert_eq!(MerkleTree::verify_proof(proof.clone(), value.clone(), node_index, root_hash), Ok(()));
+				assert_eq!(client_tree.verify_proof(&proof, value.clone(), node_index, root_hash), Ok(()));
+
+				// Standalone hasher doesn't depend on substrate, but has to hash the same way
+				let leaf_hash = Blake2Hasher::hash_leaf(value);
+				assert_eq!(leaf_hash, BlakeTwo256::hash_of(value).0);
+				let raw_proof: Vec<Option<[u8; 32]>> = proof.iter().map(|sibling| sibling.map(|hash| hash.0)).collect();
+				let res = merkle_proof::verify_proof::<Blake2Hasher>(&raw_proof, leaf_hash, node_index, n_nodes, root_hash.0);
+				assert_eq!(res, Ok(()));
+
+				// Wrong index is rejected by all of them
+				let wrong_index = (node_index + 1) % n_nodes;
+				assert_eq!(MerkleTree::verify_proof(proof.clone(), value.clone(), wrong_index, root_hash), Err("Proof not valid"));
+				assert_eq!(client_tree.verify_proof(&proof, value.clone(), wrong_index, root_hash), Err("Proof not valid"));
+				let res = merkle_proof::verify_proof::<Blake2Hasher>(&raw_proof, leaf_hash, wrong_index, n_nodes, root_hash.0);
+				assert_eq!(res, Err(merkle_proof::Error::InvalidProof));
+			}
+		});
+	}
+
+	fn hash_from_hex(hex: &str) -> H256 {
+		let bytes: Vec<u8> = (2..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+		H256::from_slice(&bytes)
+	}
+
+	#[test]
+	fn should_match_proof_fixtures_of_wasm_verifier() {
+		// Same fixtures are checked against the wasm build in `merkle-proof-wasm/tests/verify.js`
+		let fixtures: serde_json::Value = serde_json::from_str(include_str!("../../merkle-proof-wasm/tests/fixtures.json")).unwrap();
+
+		with_externalities(&mut new_test_ext(), || {
+			for value in fixtures["values"].as_array().unwrap() {
+				let result = MerkleTree::insert(Origin::signed(1), value.as_str().unwrap().as_bytes().to_vec());
+				assert_eq!(result, Ok(()));
+			}
+			assert_eq!(MerkleTree::n_nodes(), fixtures["n_nodes"].as_u64().unwrap() as u128);
+
+			for case in fixtures["cases"].as_array().unwrap() {
+				let proof = case["proof"].as_array().unwrap().iter().map(|sibling| sibling.as_str().map(hash_from_hex)).collect();
+				let value = case["leaf"].as_str().unwrap().as_bytes().to_vec();
+				let node_index = case["node_index"].as_u64().unwrap() as u128;
+				let root_hash = hash_from_hex(case["root"].as_str().unwrap());
+				let res = MerkleTree::verify_proof(proof, value, node_index, root_hash);
+				assert_eq!(res.is_ok(), case["valid"].as_bool().unwrap(), "{}", case["description"]);
+			}
+		});
+	}
+
+	#[test]
+	fn should_be_able_to_roll_back_to_previous_block() {
+		with_externalities(&mut new_test_ext(), || {
+			let a = "a".to_string().as_bytes().to_vec();
+			let b = "b".to_string().as_bytes().to_vec();
+			let c = "c".to_string().as_bytes().to_vec();
+			let d = "d".to_string().as_bytes().to_vec();
+			let e = "e".to_string().as_bytes().to_vec();
+			let a_hash = BlakeTwo256::hash_of(&a);
+			let b_hash = BlakeTwo256::hash_of(&b);
+			let c_hash = BlakeTwo256::hash_of(&c);
+
+			// Canonical chain only contains `a`, `b`, `d` and `e`
+			for value in vec![a.clone(), b.clone(), d.clone(), e.clone()] {
+				let result = MerkleTree::insert(Origin::signed(1), value);
+				assert_eq!(result, Ok(()));
+			}
+			let root_hash = MerkleTree::root_hash();
+			let events = get_event_values();
+
+			let block_1 = BlakeTwo256::hash_of(&1u64);
+			let block_2a = BlakeTwo256::hash_of(&2u64);
+			let block_2b = BlakeTwo256::hash_of(&3u64);
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_block(block_1, events[..2].to_vec()), Ok(()));
+			assert_eq!(client_tree.build_tree_from_block(block_2a, insert_events(&[a_hash, b_hash], &[c_hash])), Ok(()));
+			assert_ne!(client_tree.root_hash, root_hash);
+
+			// Node switches to the fork containing `d` and `e` instead of `c`
+			let result = client_tree.reorg(RollbackTarget::Block(block_1), vec![(block_2b, events[2..].to_vec())]);
+			assert_eq!(result, Ok(()));
+			assert!(client_tree.has_block(&block_1));
+			assert!(client_tree.has_block(&block_2b));
+			assert!(!client_tree.has_block(&block_2a));
+
+			let mut synced_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(synced_tree.build_tree_from_events(get_event_values()), Ok(()));
+
+			assert_eq!(client_tree.root_hash, root_hash);
+			assert_eq!(client_tree.n_nodes, 4);
+			assert_eq!(client_tree.edge_nodes, synced_tree.edge_nodes);
+			assert_eq!(client_tree.node_indices, synced_tree.node_indices);
+			assert_eq!(client_tree.node_indices.get(&c_hash), None);
+
+			let node_index = client_tree.get_node_index(e.clone());
+			let proof = client_tree.get_proof_for(e.clone(), root_hash.unwrap());
+			let res = MerkleTree::verify_proof(proof.unwrap(), e.clone(), node_index, root_hash.unwrap());
+			assert_eq!(res, Ok(()));
+
+			// Orphaned block is forgotten
+			let result = client_tree.rollback_to(RollbackTarget::Block(block_2a));
+			assert_eq!(result, Err("Block not found in client history!"));
+		});
+	}
+
+	#[test]
+	fn should_get_roots_of_earlier_tree_states() {
+		with_externalities(&mut new_test_ext(), || {
+			let a = "a".to_string().as_bytes().to_vec();
+			let b = "b".to_string().as_bytes().to_vec();
+			let c = "c".to_string().as_bytes().to_vec();
+
+			let mut result = MerkleTree::insert(Origin::signed(1), a.clone());
+			assert_eq!(result, Ok(()));
+			result = MerkleTree::insert(Origin::signed(1), b.clone());
+			assert_eq!(result, Ok(()));
+			let root_hash_after_b = MerkleTree::root_hash();
+			result = MerkleTree::insert(Origin::signed(1), c.clone());
+			assert_eq!(result, Ok(()));
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(get_event_values()), Ok(()));
+			assert_eq!(client_tree.get_root_at(0), None);
+			assert_eq!(client_tree.get_root_at(2), root_hash_after_b);
+			assert_eq!(client_tree.get_root_at(3), MerkleTree::root_hash());
+			assert_eq!(client_tree.get_root_at(4), None);
+		});
+	}
+
+	#[test]
+	fn should_be_able_to_roll_back_to_number_of_nodes() {
+		with_externalities(&mut new_test_ext(), || {
+			let a = "a".to_string().as_bytes().to_vec();
+			let b = "b".to_string().as_bytes().to_vec();
+			let c = "c".to_string().as_bytes().to_vec();
+
+			let mut result = MerkleTree::insert(Origin::signed(1), a.clone());
+			assert_eq!(result, Ok(()));
+			result = MerkleTree::insert(Origin::signed(1), b.clone());
+			assert_eq!(result, Ok(()));
+
+			let root_hash_after_b = MerkleTree::root_hash();
+			let edge_nodes_after_b = MerkleTree::edge_nodes();
+
+			result = MerkleTree::insert(Origin::signed(1), c.clone());
+			assert_eq!(result, Ok(()));
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(get_event_values()), Ok(()));
+
+			result = client_tree.rollback_to(RollbackTarget::NNodes(4));
+			assert_eq!(result, Err("Can't roll back to a state newer than the current one!"));
+
+			result = client_tree.rollback_to(RollbackTarget::NNodes(2));
+			assert_eq!(result, Ok(()));
+			assert_eq!(client_tree.root_hash, root_hash_after_b);
+			assert_eq!(client_tree.edge_nodes, edge_nodes_after_b);
+			assert_eq!(client_tree.n_nodes, 2);
+			assert_eq!(client_tree.leaves.len(), 2);
+
+			result = client_tree.rollback_to(RollbackTarget::NNodes(0));
+			assert_eq!(result, Ok(()));
+			assert_eq!(client_tree.root_hash, None);
+			assert_eq!(client_tree.edge_nodes, vec![]);
+			assert!(client_tree.node_indices.is_empty());
+		});
+	}
+
+	#[test]
+	fn should_detect_events_that_do_not_follow_from_the_client_tree() {
+		with_externalities(&mut new_test_ext(), || {
+			for value in "abcd".chars() {
+				let result = MerkleTree::insert(Origin::signed(1), value.to_string().as_bytes().to_vec());
+				assert_eq!(result, Ok(()));
+			}
+			let events = get_event_values();
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(events[..2].to_vec()), Ok(()));
+			let root_hash = client_tree.root_hash;
+
+			// Missed event
+			assert_eq!(client_tree.build_tree_from_events(vec![events[3]]), Err(SyncDivergence::UnexpectedIndex));
+			// Repeated event
+			assert_eq!(client_tree.build_tree_from_events(vec![events[1]]), Err(SyncDivergence::UnexpectedIndex));
+			// Event of a different tree, none of the events are applied
+			let (index, leaf, _) = events[3];
+			let result = client_tree.build_tree_from_events(vec![events[2], (index, leaf, H256::zero())]);
+			assert_eq!(result, Err(SyncDivergence::RootMismatch));
+			assert_eq!(client_tree.root_hash, root_hash);
+			assert_eq!(client_tree.n_nodes, 2);
+			assert_eq!(client_tree.get_root_at(3), None);
+
+			assert_eq!(client_tree.build_tree_from_events(events[2..].to_vec()), Ok(()));
+			assert_eq!(client_tree.root_hash, MerkleTree::root_hash());
+
+			// Diverged block is not remembered
+			let block = BlakeTwo256::hash_of(&1u64);
+			let result = client_tree.build_tree_from_block(block, vec![(4, leaf, H256::zero())]);
+			assert_eq!(result, Err(SyncDivergence::RootMismatch));
+			assert!(!client_tree.has_block(&block));
+			assert_eq!(client_tree.n_nodes, 4);
+		});
+	}
+
+	#[test]
+	fn should_commit_root_into_headers_of_blocks_that_changed_the_tree() {
+		with_externalities(&mut new_test_ext(), || {
+			System::initialise(&1, &Default::default(), &Default::default());
+			<MerkleTree as OnFinalise<u64>>::on_finalise(1);
+			assert_eq!(root_from_header(&System::finalise()), None);
+
+			System::initialise(&2, &Default::default(), &Default::default());
+			for value in "abc".chars() {
+				let result = MerkleTree::insert(Origin::signed(1), value.to_string().as_bytes().to_vec());
+				assert_eq!(result, Ok(()));
+			}
+			<MerkleTree as OnFinalise<u64>>::on_finalise(2);
+			let header = System::finalise();
+			assert_eq!(header.digest().logs().len(), 1);
+			assert_eq!(root_from_header(&header), Some((MerkleTree::root_hash().unwrap(), 3)));
+
+			// Block without inserts doesn't commit the unchanged root again
+			System::initialise(&3, &Default::default(), &Default::default());
+			<MerkleTree as OnFinalise<u64>>::on_finalise(3);
+			assert_eq!(root_from_header(&System::finalise()), None);
+		});
+	}
+
+	#[test]
+	fn should_aggregate_inserts_of_a_block_into_the_same_tree() {
+		let values: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i]).collect();
+		let (root_hash, events) = with_externalities(&mut new_test_ext(), || {
+			System::initialise(&1, &Default::default(), &Default::default());
+			for value in values.iter() {
+				assert_eq!(MerkleTree::insert(Origin::signed(1), value.clone()), Ok(()));
+			}
+			<MerkleTree as OnFinalise<u64>>::on_finalise(1);
+			(MerkleTree::root_hash(), get_event_values())
+		});
+
+		with_externalities(&mut aggregated::new_test_ext(), || {
+			aggregated::System::initialise(&1, &Default::default(), &Default::default());
+			for value in values.iter() {
+				assert_eq!(aggregated::MerkleTree::insert(aggregated::Origin::signed(1), value.clone()), Ok(()));
+			}
+			// Tree only changes once the block is finalised
+			assert_eq!(aggregated::MerkleTree::root_hash(), None);
+			assert_eq!(aggregated::MerkleTree::n_nodes(), 0);
+
+			<aggregated::MerkleTree as OnFinalise<u64>>::on_finalise(1);
+			assert_eq!(aggregated::MerkleTree::root_hash(), root_hash);
+			assert_eq!(aggregated::MerkleTree::n_nodes(), 20);
+			assert_eq!(aggregated::get_event_values(), events);
+			assert_eq!(root_from_header(&aggregated::System::finalise()), Some((root_hash.unwrap(), 20)));
+
+			// Queue is emptied, so the next block starts from the new tree
+			aggregated::System::initialise(&2, &Default::default(), &Default::default());
+			assert_eq!(aggregated::MerkleTree::insert(aggregated::Origin::signed(1), vec![20]), Ok(()));
+			<aggregated::MerkleTree as OnFinalise<u64>>::on_finalise(2);
+			assert_eq!(aggregated::MerkleTree::n_nodes(), 21);
+			assert_eq!(aggregated::get_event_values(), vec![(20, BlakeTwo256::hash_of(&vec![20u8]), aggregated::MerkleTree::root_hash().unwrap())]);
+
+			// Leaves are not stored by this runtime
+			assert_eq!(aggregated::MerkleTree::leaf_at(0), None);
+			assert_eq!(aggregated::MerkleTree::proof_at(0), None);
+		});
+	}
+
+	#[test]
+	fn should_prove_typed_leaves() {
+		use self::typed::Record;
+
+		with_externalities(&mut typed::new_test_ext(), || {
+			let records: Vec<Record> = (0..5).map(|i| Record { account: i, amount: i as u128 * 100 }).collect();
+			for record in records.iter() {
+				assert_eq!(typed::MerkleTree::insert(typed::Origin::signed(1), record.clone()), Ok(()));
+			}
+
+			// Leaf is the hash of the SCALE encoding of the record
+			let events = typed::get_event_values();
+			assert_eq!(events[2].1, BlakeTwo256::hash_of(&records[2]));
+			assert_eq!(events[2].1, ClientHasher::hash_leaf(&records[2]));
+			assert_eq!(events[2].1, TreeHasher::<typed::TypedTest>::hash_leaf(&records[2]));
+
+			let root_hash = typed::MerkleTree::root_hash().unwrap();
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None).with_leaf_type::<Record>();
+			assert_eq!(client_tree.build_tree_from_events(events), Ok(()));
+			let proof = client_tree.get_proof_for(records[2].clone(), root_hash).unwrap();
+			assert_eq!(client_tree.verify_proof(&proof, records[2].clone(), 2, root_hash), Ok(()));
+			assert_eq!(typed::MerkleTree::verify_proof(proof.clone(), records[2].clone(), 2, root_hash), Ok(()));
+
+			let forged = Record { account: 2, amount: 1_000_000 };
+			assert!(client_tree.verify_proof(&proof, forged.clone(), 2, root_hash).is_err());
+			assert!(typed::MerkleTree::verify_proof(proof, forged, 2, root_hash).is_err());
+		});
+	}
+
+	#[test]
+	fn should_insert_pre_hashed_leaves() {
+		with_externalities(&mut new_test_ext(), || {
+			let document = b"document".to_vec();
+			let digest = BlakeTwo256::hash_of(&b"digest of a document".to_vec());
+			assert_eq!(MerkleTree::insert(Origin::signed(1), document.clone()), Ok(()));
+			assert_eq!(MerkleTree::insert_hash(Origin::signed(1), digest), Ok(()));
+
+			// Leaf is the digest itself
+			let root_hash = MerkleTree::root_hash().unwrap();
+			let events = get_event_values();
+			assert_eq!(events[1], (1, digest, root_hash));
+			assert_eq!(MerkleTree::leaf_at(1), Some(digest));
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(events), Ok(()));
+			let proof = client_tree.get_proof_for_hash(digest, root_hash).unwrap();
+			assert_eq!(client_tree.verify_proof_for_hash(&proof, digest, 1, root_hash), Ok(()));
+			assert_eq!(MerkleTree::verify_proof_for_hash(proof.clone(), digest, 1, root_hash), Ok(()));
+			// Digest is not the value of the leaf
+			assert!(MerkleTree::verify_proof(proof, digest.as_ref().to_vec(), 1, root_hash).is_err());
+
+			// Leaves inserted by value can be found by their hash as well
+			let document_proof = client_tree.get_proof_for(document.clone(), root_hash).unwrap();
+			assert_eq!(client_tree.get_proof_for_hash(BlakeTwo256::hash_of(&document), root_hash), Ok(document_proof));
+		});
+	}
+
+	#[test]
+	fn should_reject_duplicate_leaves_when_leaves_are_unique() {
+		with_externalities(&mut unique::new_test_ext(), || {
+			unique::System::initialise(&1, &Default::default(), &Default::default());
+			assert_eq!(unique::MerkleTree::insert(unique::Origin::signed(1), b"a".to_vec()), Ok(()));
+			// Queued leaf counts as inserted
+			assert_eq!(unique::MerkleTree::insert(unique::Origin::signed(2), b"a".to_vec()), Err("Leaf is already in the tree"));
+			assert_eq!(unique::MerkleTree::insert(unique::Origin::signed(1), b"b".to_vec()), Ok(()));
+			<unique::MerkleTree as OnFinalise<u64>>::on_finalise(1);
+			assert_eq!(unique::MerkleTree::n_nodes(), 2);
+
+			unique::System::initialise(&2, &Default::default(), &Default::default());
+			assert_eq!(unique::MerkleTree::insert(unique::Origin::signed(1), b"b".to_vec()), Err("Leaf is already in the tree"));
+			<unique::MerkleTree as OnFinalise<u64>>::on_finalise(2);
+			assert_eq!(unique::MerkleTree::n_nodes(), 2);
+		});
+
+		// Client of a tree with unique leaves rejects events of duplicates
+		with_externalities(&mut new_test_ext(), || {
+			for value in "aba".chars() {
+				let result = MerkleTree::insert(Origin::signed(1), value.to_string().as_bytes().to_vec());
+				assert_eq!(result, Ok(()));
+			}
+			let events = get_event_values();
+
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			client_tree.unique_leaves = true;
+			assert_eq!(client_tree.build_tree_from_events(events.clone()), Err(SyncDivergence::DuplicateLeaf));
+			assert_eq!(client_tree.n_nodes, 0);
+			assert_eq!(client_tree.build_tree_from_events(events[..2].to_vec()), Ok(()));
+		});
+	}
+
+	#[test]
+	fn should_store_leaves_for_membership_and_proofs() {
+		with_externalities(&mut new_test_ext(), || {
+			let values: Vec<Vec<u8>> = vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"a".to_vec(), b"d".to_vec()];
+			for value in values.iter() {
+				assert_eq!(MerkleTree::insert(Origin::signed(1), value.clone()), Ok(()));
+			}
+
+			let a_hash = BlakeTwo256::hash_of(&values[0]);
+			assert_eq!(MerkleTree::leaf_at(3), Some(a_hash));
+			assert_eq!(MerkleTree::leaf_at(5), None);
+			assert_eq!(MerkleTree::indices_of(a_hash), vec![0, 3]);
+			assert_eq!(MerkleTree::indices_of(BlakeTwo256::hash_of(&b"x".to_vec())), vec![]);
+
+			// Proofs made from the stored leaves are the same as the ones of the client
+			let root_hash = MerkleTree::root_hash().unwrap();
+			let mut client_tree = MerkleTreeClient::with_snapshot_dir(None);
+			assert_eq!(client_tree.build_tree_from_events(get_event_values()), Ok(()));
+			for node_index in 0..5 {
+				let proof = MerkleTree::proof_at(node_index).unwrap();
+				assert_eq!(proof, client_tree.get_proof_for_index(node_index, root_hash).unwrap());
+				assert_eq!(MerkleTree::verify_proof(proof, values[node_index as usize].clone(), node_index, root_hash), Ok(()));
+			}
+			assert_eq!(MerkleTree::proof_at(5), None);
+		});
+	}
+
+	#[test]
+	fn should_migrate_edge_nodes_stored_as_a_vector() {
+		let values: Vec<Vec<u8>> = (0..7u8).map(|i| vec![i]).collect();
+		let (root_hash, edge_nodes) = with_externalities(&mut new_test_ext(), || {
+			for value in values.iter() {
+				assert_eq!(MerkleTree::insert(Origin::signed(1), value.clone()), Ok(()));
+			}
+			(MerkleTree::root_hash(), MerkleTree::edge_nodes())
+		});
+
+		with_externalities(&mut new_test_ext(), || {
+			// Tree of the first 6 values, as stored before edge nodes were stored by level
+			let mut legacy_tree = MerkleTreeClient::with_snapshot_dir(None);
+			legacy_tree.append_leaves(values[..6].iter().map(|value| BlakeTwo256::hash_of(value)).collect());
+			runtime_support::storage::put(b"MerkleTree RootHash", &legacy_tree.root_hash.unwrap());
+			runtime_support::storage::put(b"MerkleTree NNodes", &6u128);
+			runtime_support::storage::put(b"MerkleTree EdgeNodes", &legacy_tree.edge_nodes);
+
+			// Blocks without inserts leave the old layout alone
+			<MerkleTree as OnInitialise<u64>>::on_initialise(1);
+			assert_eq!(runtime_support::storage::get::<Vec<Option<H256>>>(b"MerkleTree EdgeNodes"), Some(legacy_tree.edge_nodes.clone()));
+
+			// Migrated by the first insert of the upgraded runtime
+			assert_eq!(MerkleTree::insert(Origin::signed(1), values[6].clone()), Ok(()));
+			assert_eq!(MerkleTree::root_hash(), root_hash);
+			assert_eq!(MerkleTree::edge_nodes(), edge_nodes);
+			assert_eq!(runtime_support::storage::get::<Vec<Option<H256>>>(b"MerkleTree EdgeNodes"), None);
+		});
+	}
+
+	#[test]
+	fn should_reject_proof_longer_than_tree_height() {
+		with_externalities(&mut new_test_ext(), || {
+			let a = "a".to_string().as_bytes().to_vec();
+			let b = "b".to_string().as_bytes().to_vec();
+
+			let mut result = MerkleTree::insert(Origin::signed(1), a.clone());
+			assert_eq!(result, Ok(()));
+			result = MerkleTree::insert(Origin::signed(1), b.clone());
+			assert_eq!(result, Ok(()));
+
+			let root_hash = MerkleTree::root_hash().unwrap();
+
+			// Used to overflow `2u128.pow(i)` and panic
+			let proof = vec![Some(H256::zero()); 200];
+			result = MerkleTree::verify_proof(proof, a.clone(), 0, root_hash);
+			assert_eq!(result, Err("Proof is too long"));
+
+			// One level too many, even though the prefix is valid
+			let b_hash = BlakeTwo256::hash_of(&b);
+			let proof = vec![Some(b_hash), None];
+			result = MerkleTree::verify_proof(proof, a.clone(), 0, root_hash);
+			assert_eq!(result, Err("Proof is too long"));
+		});
+	}
+
+	#[test]
+	fn should_reject_proof_shorter_than_node_index() {
+		with_externalities(&mut new_test_ext(), || {
+			let a = "a".to_string().as_bytes().to_vec();
+			let b = "b".to_string().as_bytes().to_vec();
+			let c = "c".to_string().as_bytes().to_vec();
+			let d = "d".to_string().as_bytes().to_vec();
+
+			for value in vec![a, b, c, d.clone()] {
+				let result = MerkleTree::insert(Origin::signed(1), value);
+				assert_eq!(result, Ok(()));
+			}
+
+			let root_hash = MerkleTree::root_hash().unwrap();
+			let c_hash = BlakeTwo256::hash_of(&"c".to_string().as_bytes().to_vec());
+
+			// `d` has index 3, so it can't be in a tree with less than two levels
+			let result = MerkleTree::verify_proof(vec![Some(c_hash)], d.clone(), 3, root_hash);
+			assert_eq!(result, Err("Proof is too short"));
+		});
+	}
+
+	#[test]
+	fn should_reject_node_index_out_of_range() {
+		with_externalities(&mut new_test_ext(), || {
+			let a = "a".to_string().as_bytes().to_vec();
+			let b = "b".to_string().as_bytes().to_vec();
+
+			let mut result = MerkleTree::insert(Origin::signed(1), a.clone());
+			assert_eq!(result, Ok(()));
+			result = MerkleTree::insert(Origin::signed(1), b.clone());
+			assert_eq!(result, Ok(()));
+
+			let root_hash = MerkleTree::root_hash().unwrap();
+			let a_hash = BlakeTwo256::hash_of(&a);
+
+			result = MerkleTree::verify_proof(vec![Some(a_hash)], b.clone(), 2, root_hash);
+			assert_eq!(result, Err("Node index out of range"));
+			result = MerkleTree::verify_proof(vec![Some(a_hash)], b.clone(), u128::max_value(), root_hash);
+			assert_eq!(result, Err("Node index out of range"));
+		});
+	}
+
+	#[test]
+	fn should_charge_insert_fees_to_the_fee_recipient() {
+		with_externalities(&mut new_test_ext_with_fees(FeeDestination::Treasury(2)), || {
+			// Leaf fee and 5 bytes of the encoded value, its length and its 4 bytes
+			assert_eq!(MerkleTree::insert_fee(5), Some(15));
+			assert_eq!(MerkleTree::insert(Origin::signed(1), vec![1, 2, 3, 4]), Ok(()));
+			// Leaf inserted by hash is charged for the 32 bytes of the hash
+			let leaf = BlakeTwo256::hash_of(&vec![5u8]);
+			assert_eq!(MerkleTree::insert_hash(Origin::signed(1), leaf), Ok(()));
+
+			assert_eq!(Balances::free_balance(1), 100 - 15 - 42);
+			assert_eq!(Balances::free_balance(2), 100 + 15 + 42);
+			assert_eq!(Balances::total_issuance(), 210);
+			let fees: Vec<(u64, u64)> = System::events().into_iter().filter_map(|event_record| match event_record.event {
+				Event::merkle_tree(RawEvent::FeePaid(who, fee)) => Some((who, fee)),
+				_ => None,
+			}).collect();
+			assert_eq!(fees, vec![(1, 15), (1, 42)]);
+
+			// Insert that can't be paid for doesn't change the tree
+			let root_hash = MerkleTree::root_hash();
+			assert_eq!(MerkleTree::insert(Origin::signed(3), vec![1, 2, 3, 4]), Err("Not enough funds for the insert fee"));
+			assert_eq!(MerkleTree::n_nodes(), 2);
+			assert_eq!(MerkleTree::root_hash(), root_hash);
+			assert_eq!(Balances::free_balance(3), 10);
+		});
+	}
+
+	#[test]
+	fn should_burn_insert_fees_without_a_treasury() {
+		with_externalities(&mut new_test_ext_with_fees(FeeDestination::Burn), || {
+			assert_eq!(MerkleTree::insert(Origin::signed(1), vec![1, 2, 3, 4]), Ok(()));
+
+			assert_eq!(Balances::free_balance(1), 85);
+			assert_eq!(Balances::free_balance(2), 100);
+			assert_eq!(Balances::total_issuance(), 210 - 15);
+		});
+	}
+
+	#[test]
+	fn should_reject_inserts_whose_fee_overflows() {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(balances::GenesisConfig::<Test> {
+			balances: vec![(1, u64::max_value())],
+			transaction_base_fee: 0,
+			transaction_byte_fee: 0,
+			existential_deposit: 1,
+			transfer_fee: 0,
+			creation_fee: 0,
+			reclaim_rebate: 0,
+		}.build_storage().unwrap().0);
+		// Byte fee of 4 bytes doesn't fit, leaf fee and byte fee of 3 bytes add up to the maximum balance
+		t.extend(GenesisConfig::<Test> {
+			leaf_fee: u64::max_value() / 4,
+			byte_fee: u64::max_value() / 4 + 1,
+			..Default::default()
+		}.build_storage().unwrap().0);
+		let mut ext: runtime_io::TestExternalities<Blake2Hasher> = t.into();
+
+		with_externalities(&mut ext, || {
+			assert_eq!(MerkleTree::insert_fee(4), None);
+			assert_eq!(MerkleTree::insert(Origin::signed(1), vec![1, 2, 3]), Err("Insert fee overflows"));
+			// Fee fits, but not together with the existential deposit
+			assert_eq!(MerkleTree::insert_fee(3), Some(u64::max_value()));
+			assert_eq!(MerkleTree::insert(Origin::signed(1), vec![1, 2]), Err("Insert fee overflows"));
+			assert_eq!(MerkleTree::n_nodes(), 0);
+
+			let fee = MerkleTree::insert_fee(2).unwrap();
+			assert_eq!(MerkleTree::insert(Origin::signed(1), vec![1]), Ok(()));
+			assert_eq!(MerkleTree::n_nodes(), 1);
+			assert_eq!(Balances::free_balance(1), u64::max_value() - fee);
+		});
+	}
+
+	#[test]
+	fn should_limit_inserts_per_block_and_per_account_per_era() {
+		with_externalities(&mut new_test_ext_with_limits(3), || {
+			System::initialise(&1, &Default::default(), &Default::default());
+			<MerkleTree as OnInitialise<u64>>::on_initialise(1);
+			assert_eq!(MerkleTree::insert(Origin::signed(1), vec![1]), Ok(()));
+			assert_eq!(MerkleTree::insert_hash(Origin::signed(1), BlakeTwo256::hash_of(&vec![2u8])), Ok(()));
+			assert_eq!(MerkleTree::insert(Origin::signed(1), vec![3]), Err("Account already made the maximum number of inserts in this era"));
+			assert_eq!(MerkleTree::insert(Origin::signed(2), vec![4]), Ok(()));
+			assert_eq!(MerkleTree::insert(Origin::signed(3), vec![5]), Err("Block already has the maximum number of inserts"));
+			assert_eq!(MerkleTree::n_nodes(), 3);
+			// Next block is in the same era
+			assert_eq!(MerkleTree::can_insert(&1), Err("Account already made the maximum number of inserts in this era"));
+			assert_eq!(MerkleTree::can_insert(&3), Ok(()));
+			<MerkleTree as OnFinalise<u64>>::on_finalise(1);
+			System::finalise();
+
+			// Block counter is reset, account counters are not
+			System::initialise(&2, &Default::default(), &Default::default());
+			<MerkleTree as OnInitialise<u64>>::on_initialise(2);
+			assert_eq!(MerkleTree::insert(Origin::signed(3), vec![5]), Ok(()));
+			assert_eq!(MerkleTree::insert(Origin::signed(1), vec![3]), Err("Account already made the maximum number of inserts in this era"));
+			// Next block starts a new era
+			assert_eq!(MerkleTree::can_insert(&1), Ok(()));
+			<MerkleTree as OnFinalise<u64>>::on_finalise(2);
+			System::finalise();
+
+			System::initialise(&3, &Default::default(), &Default::default());
+			<MerkleTree as OnInitialise<u64>>::on_initialise(3);
+			assert_eq!(MerkleTree::insert(Origin::signed(1), vec![3]), Ok(()));
+			// Counters of the previous era are left as they are
+			assert_eq!(MerkleTree::inserts_in_era(&(1, 1)), 1);
+			assert_eq!(MerkleTree::inserts_in_era(&(1, 2)), 0);
+			assert_eq!(MerkleTree::inserts_in_era(&(0, 1)), 2);
+			assert_eq!(MerkleTree::inserts_in_era(&(0, 2)), 1);
+			assert_eq!(MerkleTree::n_nodes(), 5);
+		});
+	}
+
+	#[test]
+	fn should_never_reset_account_limit_without_eras() {
+		with_externalities(&mut new_test_ext_with_limits(0), || {
+			for n in 1..4 {
+				System::initialise(&n, &Default::default(), &Default::default());
+				<MerkleTree as OnInitialise<u64>>::on_initialise(n);
+				let result = MerkleTree::insert(Origin::signed(1), vec![n as u8]);
+				assert_eq!(result, if n < 3 { Ok(()) } else { Err("Account already made the maximum number of inserts in this era") });
+				<MerkleTree as OnFinalise<u64>>::on_finalise(n);
+				System::finalise();
+			}
+			assert_eq!(MerkleTree::can_insert(&1), Err("Account already made the maximum number of inserts in this era"));
+			// Every block is in era zero
+			assert_eq!(MerkleTree::inserts_in_era(&(0, 1)), 2);
+		});
+	}
 }
@@ -7,14 +7,108 @@ extern crate sr_std as rstd;
 extern crate substrate_primitives as primitives;
 
 extern crate srml_system as system;
+extern crate srml_balances as balances;
 
-use runtime_support::dispatch::Result;
-use runtime_support::StorageValue;
-use runtime_primitives::traits::{Hash};
+use parity_codec::Encode;
+use runtime_support::dispatch::{Parameter, Result};
+use runtime_support::{StorageMap, StorageValue};
+use runtime_primitives::traits::{As, CheckedAdd, CheckedMul, Digest, Hash, Header, One, Zero};
+use balances::EnsureAccountLiquid;
 use rstd::prelude::*;
+use rstd::marker::PhantomData;
+use merkle_proof::Hasher;
+use system::ensure_signed;
+
+pub trait Trait: balances::Trait {
+    // Values leaves are made from, hashed by their SCALE encoding. `Vec<u8>` keeps leaves as raw bytes
+    type Leaf: Parameter;
+
+    // Queue inserts during the block and add them to the tree once in `on_finalise`, instead of
+    // updating the tree on every insert. Final root and events are the same in both modes
+    const AGGREGATE_INSERTS: bool;
+    // Keep every leaf hash and the indices of every leaf hash in storage, so the chain can answer
+    // membership queries and create proofs without a client side index
+    const STORE_LEAVES: bool;
+    // Reject inserts of leaves that are already in the tree, so every leaf hash has a single index
+    const UNIQUE_LEAVES: bool;
 
-pub trait Trait: system::Trait {
     type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+    // Digest item type the root of the tree is committed to the header with
+    type Log: From<Log<Self>> + Into<system::DigestItemOf<Self>>;
+}
+
+pub type Log<T> = RawLog<<T as system::Trait>::Hash>;
+
+// Digest item deposited by every block that changed the tree, so light clients can learn the root from headers
+#[cfg_attr(feature = "std", derive(serde_derive::Serialize, Debug))]
+#[derive(Encode, Decode, PartialEq, Eq, Clone)]
+pub enum RawLog<Hash> {
+    // Root hash and number of leaves at the end of the block
+    RootChange(Hash, u128),
+}
+
+// Digest item that may carry the root of the tree, implemented by the runtime's log type
+pub trait RootDigestItem<Hash> {
+    fn as_root_change(&self) -> Option<(Hash, u128)>;
+}
+
+impl<Hash: Clone> RootDigestItem<Hash> for RawLog<Hash> {
+    fn as_root_change(&self) -> Option<(Hash, u128)> {
+        match *self {
+            RawLog::RootChange(ref root_hash, n_nodes) => Some((root_hash.clone(), n_nodes)),
+        }
+    }
+}
+
+// Account the fees of inserts are paid to
+#[cfg_attr(feature = "std", derive(serde_derive::Serialize, serde_derive::Deserialize, Debug))]
+#[derive(Encode, Decode, PartialEq, Eq, Clone)]
+pub enum FeeDestination<AccountId> {
+    // Fees are taken out of the total issuance
+    Burn,
+    // Fees are added to the free balance of the account, created if it doesn't exist
+    Treasury(AccountId),
+}
+
+impl<AccountId> Default for FeeDestination<AccountId> {
+    fn default() -> Self {
+        FeeDestination::Burn
+    }
+}
+
+// Root hash and number of leaves committed to by `header`, `None` if its block didn't change the tree
+pub fn root_from_header<H>(header: &H) -> Option<(H::Hash, u128)> where
+    H: Header,
+    <H::Digest as Digest>::Item: RootDigestItem<H::Hash>,
+{
+    header.digest().logs().iter().filter_map(|item| item.as_root_change()).last()
+}
+
+// Hashing of the tree nodes used by `insert`, for verifying proofs with `merkle_proof`
+pub struct TreeHasher<T>(PhantomData<T>);
+
+impl<T: Trait> TreeHasher<T> {
+    // Hash of a leaf of any type, made from its encoding the same way `insert` hashes `T::Leaf`
+    pub fn hash_leaf<L: Encode + ?Sized>(leaf: &L) -> T::Hash {
+        T::Hashing::hash(&leaf.encode())
+    }
+}
+
+impl<T: Trait> Hasher for TreeHasher<T> {
+    type Out = T::Hash;
+
+    // Leaf made from raw bytes, a `Vec<u8>` leaf
+    fn hash_leaf(value: &[u8]) -> T::Hash {
+        TreeHasher::<T>::hash_leaf(value)
+    }
+
+    fn hash_pair(left: &T::Hash, right: &T::Hash) -> T::Hash {
+        T::Hashing::hash_of(&[*left, *right])
+    }
+
+    fn hash_single(node: &T::Hash) -> T::Hash {
+        T::Hashing::hash_of(node)
+    }
 }
 
 decl_storage! {
@@ -23,82 +117,343 @@ decl_storage! {
         RootHash get(root_hash): Option<T::Hash>;
         // Number of nodes in the tree
         NNodes get(n_nodes): u128;
-        // Hashes of the edge nodes needed for pairing with next insert
-        EdgeNodes get(edge_nodes): Vec<Option<T::Hash>>;
+        // Hashes of the edge nodes needed for pairing with next insert, by level
+        // An insert reads every level once to compute the root, but only writes the levels whose edge
+        // node changed. A single vector was read and written in full instead, 33 bytes per level.
+        // Edge storage accesses per insert, averaged over 1000 inserts into a tree of the given size
+        // (`edge_layouts` in the aggregation bench):
+        //   leaves     vector: reads  writes  bytes written   by level: reads  writes  bytes written
+        //   1000               1      1       202                     12      2       32
+        //   1000000            1      1       359                     21      2       32
+        //   1000000000         1      1       589                     31      2       32
+        // Bytes read are about the same, so levels trade more, smaller accesses for fewer bytes written
+        EdgeNodeAt: map u32 => Option<T::Hash>;
+        // Number of levels in `EdgeNodeAt`, including the ones without an edge node
+        EdgeLevels get(edge_levels): u32;
+        // Edge nodes stored as a single vector by earlier versions, moved into `EdgeNodeAt` by the first insert
+        EdgeNodes: Option<Vec<Option<T::Hash>>>;
+        // Hash of the leaf at every index, when leaves are stored
+        LeafAt get(leaf_at): map u128 => Option<T::Hash>;
+        // Indices a leaf hash was inserted at, in order of insertion, when leaves are stored
+        IndexOf get(indices_of): map T::Hash => Vec<u128>;
+        // Leaf hashes inserted so far, including the queued ones, when leaves have to be unique
+        Inserted get(is_inserted): map T::Hash => bool;
+        // Whether the tree changed in the current block, cleared when the block is finalised
+        DidUpdate: bool;
+        // Leaf hashes inserted in the current block, by position, when inserts are aggregated
+        PendingLeaves: map u32 => Option<T::Hash>;
+        // Number of leaf hashes in `PendingLeaves`
+        PendingCount: u32;
+        // Fee paid for every inserted leaf, on top of the transaction fee
+        LeafFee get(leaf_fee) config(): T::Balance;
+        // Fee paid for every byte of the encoded value of an insert
+        ByteFee get(byte_fee) config(): T::Balance;
+        // Where insert fees go
+        FeeRecipient get(fee_recipient) config(): FeeDestination<T::AccountId>;
+        // Most inserts a block can have, no limit if zero
+        MaxInsertsPerBlock get(max_inserts_per_block) config(): u32;
+        // Most inserts an account can make in an era, no limit if zero
+        MaxInsertsPerAccountPerEra get(max_inserts_per_account_per_era) config(): u32;
+        // Number of blocks in an era, eras start at multiples of it. Zero makes the account limit last forever
+        InsertEraLength get(insert_era_length) config(): T::BlockNumber;
+        // Inserts made in the current block, cleared when the next block is initialised
+        InsertsInBlock get(inserts_in_block): u32;
+        // Inserts made by an account in an era, by era index and account, when the account limit is set
+        // Keyed by era so nothing has to be cleared when an era starts, counters of past eras are just never read
+        InsertsInEra get(inserts_in_era): map (T::BlockNumber, T::AccountId) => u32;
     }
 }
 
 decl_event!(
     // Event fired when new addition is added. Whole tree can be derived on client from these events
-    pub enum Event<T> where <T as system::Trait>::Hash {
-		Insert(Hash),
+    pub enum Event<T> where <T as system::Trait>::Hash, <T as system::Trait>::AccountId, <T as balances::Trait>::Balance {
+		// Index of the new leaf, hash of the leaf and root hash of the tree after the insert
+		// Index and root let clients detect missed or repeated events
+		Insert(u128, Hash, Hash),
+		// Account that inserted and the fee it paid, only fired when the fee isn't zero
+		FeePaid(AccountId, Balance),
 	}
 );
 
 decl_module! {
     pub struct Module<T: Trait> for enum Call where origin: T::Origin {
         fn deposit_event<T>() = default;
-        pub fn insert(value: Vec<u8>) -> Result {
+        pub fn insert(origin, value: T::Leaf) -> Result {
+            // Any account can insert, as a signed extrinsic
+            let sender = ensure_signed(origin)?;
+
             // Make a hash of value
             let value_hash = T::Hashing::hash_of(&value);
+            Self::insert_leaf(&sender, value_hash, value.encode().len())
+        }
 
-            // Pair node used for hashing with edge node
-            let mut pair_hash = value_hash;
-            let mut new_edge = value_hash;
-            let n_nodes = Self::n_nodes();
-            // Get the level on which will be the next new edge node
-            let next_edge_addition_level = Self::count_bit_set_from_right(n_nodes);
-
-            let edge_nodes = Self::edge_nodes();
-            // Loop trought all levels of the tree
-            for i in 0..edge_nodes.len() {
-                let edge_node = edge_nodes[i];
-                pair_hash = match edge_node {
-                    // There is edge node on this level, make a hash of the pair
-                    Some(hash) => T::Hashing::hash_of(&[hash, pair_hash]),
-                    // There is no edge node on this level, hash itself then
-                    None => T::Hashing::hash_of(&pair_hash)
-                };
-                if (i + 1) as u8 == next_edge_addition_level {
-                    // Hash on this level is new edge
-                    new_edge = pair_hash;
+        // Insert a leaf that was hashed off-chain, so only the digest has to be uploaded
+        // Leaf is `leaf` itself, so it is identified by hash rather than by value
+        pub fn insert_hash(origin, leaf: T::Hash) -> Result {
+            let sender = ensure_signed(origin)?;
+            Self::insert_leaf(&sender, leaf, leaf.encode().len())
+        }
+
+        // Reset the insert counter of the previous block
+        fn on_initialise(_n: T::BlockNumber) {
+            <InsertsInBlock<T>>::kill();
+        }
+
+        // Add queued inserts to the tree and commit its root into the header of the block, if the block changed the tree
+        fn on_finalise() {
+            if T::AGGREGATE_INSERTS {
+                let leaves = (0..<PendingCount<T>>::take())
+                    .filter_map(|i| <PendingLeaves<T>>::take(i))
+                    .collect();
+                Self::insert_leaves(leaves);
+            }
+
+            if <DidUpdate<T>>::take() {
+                if let Some(root_hash) = Self::root_hash() {
+                    let log: Log<T> = RawLog::RootChange(root_hash, Self::n_nodes());
+                    <system::Module<T>>::deposit_log(<T as Trait>::Log::from(log).into());
                 }
             }
+        }
 
-            // Update the root hash
-            <RootHash<T>>::put(pair_hash);
-            <NNodes<T>>::put(n_nodes + 1);
-            // Update edge nodes
-            Self::update_edges(edge_nodes, new_edge, next_edge_addition_level as usize);
+        // Proove that `value` hash index of `node_index` and that it exists inside `root_hash` state
+        pub fn verify_proof(proof: Vec<Option<T::Hash>>, value: T::Leaf, node_index: u128, root_hash: T::Hash) -> Result {
+            let value_hash = T::Hashing::hash_of(&value);
+            // Proof length is bounded by the current number of nodes, so long proofs are rejected before hashing
+            merkle_proof::verify_proof::<TreeHasher<T>>(&proof, value_hash, node_index, Self::n_nodes(), root_hash)
+                .map_err(|e| e.as_str())
+        }
 
-            // Emit the event so the client can sync with the contract
-            Self::deposit_event(RawEvent::Insert(value_hash));
-            Ok(())
+        // Proove that leaf `leaf` inserted by `insert_hash` has index of `node_index` and that it exists inside `root_hash` state
+        pub fn verify_proof_for_hash(proof: Vec<Option<T::Hash>>, leaf: T::Hash, node_index: u128, root_hash: T::Hash) -> Result {
+            merkle_proof::verify_proof::<TreeHasher<T>>(&proof, leaf, node_index, Self::n_nodes(), root_hash)
+                .map_err(|e| e.as_str())
         }
+    }
+}
 
-        // Proove that `value` hash index of `node_index` and that it exists inside `root_hash` state
-        pub fn verify_proof(proof: Vec<Option<T::Hash>>, value: Vec<u8>, node_index: u128, root_hash: T::Hash) -> Result {
-            let mut value_hash = T::Hashing::hash_of(&value);
-            for i in 0..proof.len() {
-                let hash = proof[i];
-                value_hash = match hash {
-                    Some(h) => {
-                        // Check if node on `i` level is left or right sibling
-                        let is_right = 2u128.pow(i as u32) & node_index != 0;
-                        let pair = if is_right {[h, value_hash]} else {[value_hash, h]};
-                        T::Hashing::hash_of(&pair)
-                    },
-                    None => T::Hashing::hash_of(&value_hash),
+impl<T: Trait> Module<T> {
+    // Hashes of the edge nodes needed for pairing with next insert, lowest level first
+    pub fn edge_nodes() -> Vec<Option<T::Hash>> {
+        Self::edge_nodes_below(Self::edge_levels())
+    }
+
+    // Edge nodes of the lowest `levels` levels
+    fn edge_nodes_below(levels: u32) -> Vec<Option<T::Hash>> {
+        (0..levels).map(|level| <EdgeNodeAt<T>>::get(level)).collect()
+    }
+
+    // Proof for the leaf at `node_index` against the current root, made from the stored leaves
+    // Reads every leaf, so it is meant for runtime API calls. `None` if leaves are not stored or
+    // were not stored from the first insert
+    pub fn proof_at(node_index: u128) -> Option<Vec<Option<T::Hash>>> {
+        let n_nodes = Self::n_nodes();
+        if !T::STORE_LEAVES || node_index >= n_nodes {
+            return None;
+        }
+        let mut nodes = (0..n_nodes).map(Self::leaf_at).collect::<Option<Vec<_>>>()?;
+        let mut index = node_index as usize;
+        let mut proof = vec![];
+        // Going up the tree one level at a time until only the root is left
+        while nodes.len() > 1 {
+            // Last node on the level doesn't have a sibling if the level has odd number of nodes
+            proof.push(nodes.get(index ^ 1).cloned());
+            nodes = nodes.chunks(2).map(|pair| match pair.get(1) {
+                Some(right) => TreeHasher::<T>::hash_pair(&pair[0], right),
+                None => TreeHasher::<T>::hash_single(&pair[0]),
+            }).collect();
+            index >>= 1;
+        }
+        Some(proof)
+    }
+
+    // Move edge nodes stored as a single vector by earlier versions into `EdgeNodeAt`
+    fn migrate_edge_nodes() {
+        if let Some(edge_nodes) = <EdgeNodes<T>>::take() {
+            for (level, edge_node) in edge_nodes.iter().enumerate() {
+                if let Some(hash) = edge_node {
+                    <EdgeNodeAt<T>>::insert(level as u32, hash);
                 }
             }
-            ensure!(value_hash == root_hash, "Proof not valid");
-            Ok(())
+            <EdgeLevels<T>>::put(edge_nodes.len() as u32);
         }
     }
-}
 
-impl<T: Trait> Module<T> {
-    fn update_edges(mut edge_nodes: Vec<Option<T::Hash>>, new_edge_value: T::Hash, addition_at_level: usize) {
+    // Whether `who` can insert in the block after the current one, used by transaction validation
+    // Only the account limit is checked. Pool validates against the state of the last block, whose
+    // block counter says nothing about the next block, so the block limit is only enforced by `insert`
+    pub fn can_insert(who: &T::AccountId) -> Result {
+        let next_block = <system::Module<T>>::block_number() + One::one();
+        Self::check_account_limit(who, next_block)
+    }
+
+    // Index of the era of block `n`, every block is in era zero when the era length is zero
+    fn era_of(n: T::BlockNumber) -> T::BlockNumber {
+        let era_length = Self::insert_era_length();
+        if era_length.is_zero() { Zero::zero() } else { n / era_length }
+    }
+
+    fn check_rate_limits(who: &T::AccountId) -> Result {
+        let max_inserts = Self::max_inserts_per_block();
+        ensure!(max_inserts == 0 || Self::inserts_in_block() < max_inserts, "Block already has the maximum number of inserts");
+        Self::check_account_limit(who, <system::Module<T>>::block_number())
+    }
+
+    // Whether `who` is below the account limit in the era of block `n`
+    fn check_account_limit(who: &T::AccountId, n: T::BlockNumber) -> Result {
+        let max_inserts = Self::max_inserts_per_account_per_era();
+        let inserts = Self::inserts_in_era(&(Self::era_of(n), who.clone()));
+        ensure!(max_inserts == 0 || inserts < max_inserts, "Account already made the maximum number of inserts in this era");
+        Ok(())
+    }
+
+    // Count an insert of `who` against the limits
+    fn count_insert(who: &T::AccountId) {
+        <InsertsInBlock<T>>::mutate(|count| *count += 1);
+        // Accounts are only tracked when they are limited, so unlimited chains don't pay for the writes
+        if Self::max_inserts_per_account_per_era() != 0 {
+            let era = Self::era_of(<system::Module<T>>::block_number());
+            <InsertsInEra<T>>::mutate(&(era, who.clone()), |count| *count += 1);
+        }
+    }
+
+    // Fee of inserting a value of `value_len` encoded bytes, `None` if it overflows the balance type
+    pub fn insert_fee(value_len: usize) -> Option<T::Balance> {
+        Self::byte_fee()
+            .checked_mul(&<T::Balance as As<u64>>::sa(value_len as u64))?
+            .checked_add(&Self::leaf_fee())
+    }
+
+    // Take `fee` from the free balance of `who` and pay it to the fee recipient
+    // Like the transaction fee, it can't take the balance below the existential deposit
+    fn charge_fee(who: &T::AccountId, fee: T::Balance) -> Result {
+        if fee.is_zero() {
+            return Ok(());
+        }
+        let balance = <balances::Module<T>>::free_balance(who);
+        let required = fee.checked_add(&<balances::Module<T>>::existential_deposit()).ok_or("Insert fee overflows")?;
+        ensure!(balance >= required, "Not enough funds for the insert fee");
+        T::EnsureAccountLiquid::ensure_account_liquid(who)?;
+
+        <balances::Module<T>>::set_free_balance(who, balance - fee);
+        match Self::fee_recipient() {
+            FeeDestination::Burn => <balances::Module<T>>::decrease_total_stake_by(fee),
+            FeeDestination::Treasury(treasury) => {
+                <balances::Module<T>>::increase_free_balance_creating(&treasury, fee);
+            },
+        }
+        Self::deposit_event(RawEvent::FeePaid(who.clone(), fee));
+        Ok(())
+    }
+
+    // Check the insert limits of `who`, charge it for a value of `value_len` encoded bytes and add
+    // leaf `value_hash` to the tree, or to the queue of the block when inserts are aggregated
+    fn insert_leaf(who: &T::AccountId, value_hash: T::Hash, value_len: usize) -> Result {
+        Self::check_rate_limits(who)?;
+        if T::UNIQUE_LEAVES {
+            ensure!(!Self::is_inserted(value_hash), "Leaf is already in the tree");
+        }
+        // Charged before anything is written, so a failed payment leaves the tree as it was
+        let fee = Self::insert_fee(value_len).ok_or("Insert fee overflows")?;
+        Self::charge_fee(who, fee)?;
+        Self::count_insert(who);
+        if T::UNIQUE_LEAVES {
+            <Inserted<T>>::insert(value_hash, true);
+        }
+
+        if T::AGGREGATE_INSERTS {
+            // Added to the tree together with the other inserts of the block in `on_finalise`
+            let pending_count = <PendingCount<T>>::get();
+            <PendingLeaves<T>>::insert(pending_count, value_hash);
+            <PendingCount<T>>::put(pending_count + 1);
+        } else {
+            Self::insert_leaves(vec![value_hash]);
+        }
+        Ok(())
+    }
+
+    // Add `leaves` to the tree in order, reading and writing the tree's storage only once
+    fn insert_leaves(leaves: Vec<T::Hash>) {
+        if leaves.is_empty() {
+            return;
+        }
+        let mut n_nodes = Self::n_nodes();
+        let mut edge_levels = Self::edge_levels();
+        // Tree of an earlier version has leaves but no levels, so blocks without inserts never check for it
+        if n_nodes != 0 && edge_levels == 0 {
+            Self::migrate_edge_nodes();
+            edge_levels = Self::edge_levels();
+        }
+        let old_edge_nodes = Self::edge_nodes_below(edge_levels);
+        let mut edge_nodes = old_edge_nodes.clone();
+        let mut root_hash = None;
+        for value_hash in leaves {
+            let new_root = Self::append_leaf(&mut edge_nodes, n_nodes, value_hash);
+            if T::STORE_LEAVES {
+                <LeafAt<T>>::insert(n_nodes, value_hash);
+                <IndexOf<T>>::mutate(value_hash, |indices| indices.push(n_nodes));
+            }
+            // Emit the event so the client can sync with the contract
+            Self::deposit_event(RawEvent::Insert(n_nodes, value_hash, new_root));
+            n_nodes += 1;
+            root_hash = Some(new_root);
+        }
+
+        // Update the root hash
+        if let Some(root_hash) = root_hash {
+            <RootHash<T>>::put(root_hash);
+        }
+        <NNodes<T>>::put(n_nodes);
+        Self::write_edges(&old_edge_nodes, &edge_nodes);
+        <DidUpdate<T>>::put(true);
+    }
+
+    // Add leaf `value_hash` to the edge nodes of the tree with `n_nodes` leaves, returns the new root hash
+    fn append_leaf(edge_nodes: &mut Vec<Option<T::Hash>>, n_nodes: u128, value_hash: T::Hash) -> T::Hash {
+        // Pair node used for hashing with edge node
+        let mut pair_hash = value_hash;
+        let mut new_edge = value_hash;
+        // Get the level on which will be the next new edge node
+        let next_edge_addition_level = Self::count_bit_set_from_right(n_nodes);
+
+        // Loop trought all levels of the tree
+        for i in 0..edge_nodes.len() {
+            let edge_node = edge_nodes[i];
+            pair_hash = match edge_node {
+                // There is edge node on this level, make a hash of the pair
+                Some(hash) => T::Hashing::hash_of(&[hash, pair_hash]),
+                // There is no edge node on this level, hash itself then
+                None => T::Hashing::hash_of(&pair_hash)
+            };
+            if (i + 1) as u8 == next_edge_addition_level {
+                // Hash on this level is new edge
+                new_edge = pair_hash;
+            }
+        }
+
+        // Update edge nodes
+        Self::update_edges(edge_nodes, new_edge, next_edge_addition_level as usize);
+        pair_hash
+    }
+
+    // Write the levels of `edge_nodes` that are different from `old_edge_nodes`
+    fn write_edges(old_edge_nodes: &[Option<T::Hash>], edge_nodes: &[Option<T::Hash>]) {
+        for (level, edge_node) in edge_nodes.iter().enumerate() {
+            if old_edge_nodes.get(level) == Some(edge_node) {
+                continue;
+            }
+            match edge_node {
+                Some(hash) => <EdgeNodeAt<T>>::insert(level as u32, hash),
+                None => <EdgeNodeAt<T>>::remove(level as u32),
+            }
+        }
+        // Tree only grows, so levels are never removed
+        if edge_nodes.len() != old_edge_nodes.len() {
+            <EdgeLevels<T>>::put(edge_nodes.len() as u32);
+        }
+    }
+
+    fn update_edges(edge_nodes: &mut Vec<Option<T::Hash>>, new_edge_value: T::Hash, addition_at_level: usize) {
         // If edge is on one level higher that current tree height, we push the new edge
         if addition_at_level >= edge_nodes.len() {
             edge_nodes.push(Some(new_edge_value));
@@ -112,8 +467,6 @@ impl<T: Trait> Module<T> {
         for i in 0..addition_at_level {
             edge_nodes[i] = None;
         }
-
-        <EdgeNodes<T>>::put(edge_nodes);
     }
 
     // Calculating the successive number of 1 bits, starting from the right e.g.:
@@ -0,0 +1,87 @@
+// Mock runtime shared by the tests and the benches, which include this file with `#[path]`.
+// Items are declared in the module the macro is used in, paths resolve to the `merkle_tree`
+// module of this crate or to the `merkle_tree` crate.
+
+// Runtime `$runtime` with leaves of type `$leaf` and the given insert modes, together with
+// its `Origin`, `Event` and `Log`, module aliases and a fresh externalities constructor
+macro_rules! mock_runtime {
+	($runtime:ident, $leaf:ty, aggregate: $aggregate:expr, store: $store:expr, unique: $unique:expr) => {
+		impl_outer_origin! {
+			pub enum Origin for $runtime {}
+		}
+
+		impl_outer_event! {
+			pub enum Event for $runtime {
+				balances<T>,
+				merkle_tree<T>,
+			}
+		}
+
+		#[derive(Clone, Eq, PartialEq)]
+		pub struct $runtime;
+
+		// Root change log kept in the generic digest item as `Other`
+		pub struct Log(merkle_tree::RawLog<primitives::H256>);
+
+		impl From<merkle_tree::RawLog<primitives::H256>> for Log {
+			fn from(log: merkle_tree::RawLog<primitives::H256>) -> Log {
+				Log(log)
+			}
+		}
+
+		impl From<Log> for runtime_primitives::testing::DigestItem {
+			fn from(log: Log) -> runtime_primitives::testing::DigestItem {
+				runtime_primitives::generic::DigestItem::Other(codec::Encode::encode(&log.0))
+			}
+		}
+
+		impl system::Trait for $runtime {
+			type Origin = Origin;
+			type Index = u64;
+			type BlockNumber = u64;
+			type Hash = primitives::H256;
+			type Hashing = runtime_primitives::traits::BlakeTwo256;
+			type Digest = runtime_primitives::testing::Digest;
+			type AccountId = u64;
+			type Header = runtime_primitives::testing::Header;
+			type Event = Event;
+			type Log = runtime_primitives::testing::DigestItem;
+		}
+		impl balances::Trait for $runtime {
+			type Balance = u64;
+			type AccountIndex = u64;
+			type OnFreeBalanceZero = ();
+			type EnsureAccountLiquid = ();
+			type Event = Event;
+		}
+		impl merkle_tree::Trait for $runtime {
+			type Leaf = $leaf;
+			const AGGREGATE_INSERTS: bool = $aggregate;
+			const STORE_LEAVES: bool = $store;
+			const UNIQUE_LEAVES: bool = $unique;
+			type Event = Event;
+			type Log = Log;
+		}
+
+		#[allow(dead_code)]
+		pub type System = system::Module<$runtime>;
+		#[allow(dead_code)]
+		pub type Balances = balances::Module<$runtime>;
+		#[allow(dead_code)]
+		pub type MerkleTree = merkle_tree::Module<$runtime>;
+
+		#[allow(dead_code)]
+		pub fn new_test_ext() -> runtime_io::TestExternalities<primitives::Blake2Hasher> {
+			runtime_primitives::BuildStorage::build_storage(system::GenesisConfig::<$runtime>::default()).unwrap().0.into()
+		}
+
+		// Index, leaf hash and root of every insert event, any other event is unexpected
+		#[allow(dead_code)]
+		pub fn get_event_values() -> Vec<(u128, primitives::H256, primitives::H256)> {
+			System::events().into_iter().map(|event_record| match event_record.event {
+				Event::merkle_tree(merkle_tree::RawEvent::Insert(index, hash, root)) => (index, hash, root),
+				_ => panic!("hello there")
+			}).collect()
+		}
+	};
+}
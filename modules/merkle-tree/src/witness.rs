@@ -0,0 +1,266 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+extern crate sr_primitives;
+extern crate substrate_primitives;
+
+use sr_primitives::traits::BlakeTwo256;
+use runtime_primitives::traits::Hash;
+use substrate_primitives::H256;
+
+type Proof = Vec<Option<H256>>;
+type EdgeNodes = Vec<Option<H256>>;
+
+// Edge nodes of a tree, which is all that is needed to add new leaves and know the root hash
+#[derive(Default)]
+struct Frontier {
+    // Current root hash of the tree
+    root_hash: Option<H256>,
+    // Number of leaf nodes
+    n_nodes: u128,
+    // Edge nodes neccessary for the next insert
+    edge_nodes: EdgeNodes,
+}
+
+impl Frontier {
+    // Same as `insert` of the module, edge nodes are enough to compute the new root hash
+    fn insert(self: &mut Self, value_hash: H256) {
+        let mut pair_hash = value_hash;
+        let mut new_edge = value_hash;
+        // Get the level where the next edge node is
+        let next_edge_addition_level = count_bit_set_from_right(self.n_nodes);
+
+        for i in 0..self.edge_nodes.len() {
+            pair_hash = match self.edge_nodes[i] {
+                // There is edge node on this level, make a hash of the pair
+                Some(hash) => BlakeTwo256::hash_of(&[hash, pair_hash]),
+                // There is no edge node on this level, hash itself then
+                None => BlakeTwo256::hash_of(&pair_hash),
+            };
+            if i + 1 == next_edge_addition_level {
+                // Hash on this level is new edge
+                new_edge = pair_hash;
+            }
+        }
+
+        self.root_hash = Some(pair_hash);
+        self.n_nodes += 1;
+
+        // Save new edge, and remove the invalid ones
+        if next_edge_addition_level >= self.edge_nodes.len() {
+            self.edge_nodes.push(Some(new_edge));
+        } else {
+            self.edge_nodes[next_edge_addition_level] = Some(new_edge);
+        }
+        for i in 0..next_edge_addition_level {
+            self.edge_nodes[i] = None;
+        }
+    }
+
+    // Hash of this tree when it is the subtree on `level` of a bigger tree
+    // Root is hashed with itself for every level the subtree is missing
+    fn root_at_level(self: &Self, level: usize) -> Option<H256> {
+        let mut hash = self.root_hash?;
+        for _ in tree_height(self.n_nodes)..level {
+            hash = BlakeTwo256::hash_of(&hash);
+        }
+        Some(hash)
+    }
+}
+
+// Authentication path of a single watched leaf
+struct Witness {
+    // Hash of the watched leaf
+    leaf: H256,
+    // Sibling on every level, `None` if it is on the right and not complete yet
+    // Left siblings are complete once the leaf is inserted, right siblings are filled by the following inserts
+    siblings: Vec<Option<H256>>,
+    // Level and leaves of the right sibling that is currently being filled
+    cursor: Option<(usize, Frontier)>,
+}
+
+impl Witness {
+    // Adds leaf at `new_index`, inserted after the watched leaf at `node_index`, into the right sibling it belongs to
+    fn append(self: &mut Self, node_index: u128, new_index: u128, value_hash: H256) {
+        // Highest bit that differs between the indices is the level of the sibling containing the new leaf
+        let level = (127 - (node_index ^ new_index).leading_zeros()) as usize;
+        let is_same_sibling = match self.cursor {
+            Some((cursor_level, _)) => cursor_level == level,
+            None => false,
+        };
+        if !is_same_sibling {
+            self.cursor = Some((level, Frontier::default()));
+        }
+
+        let is_complete = {
+            let frontier = &mut self.cursor.as_mut().expect("Cursor was set above; qed").1;
+            frontier.insert(value_hash);
+            frontier.n_nodes == 1 << level
+        };
+        if is_complete {
+            // Sibling won't change anymore, cursor is not needed
+            let (_, frontier) = self.cursor.take().expect("Cursor was set above; qed");
+            if self.siblings.len() <= level {
+                self.siblings.resize(level + 1, None);
+            }
+            self.siblings[level] = frontier.root_hash;
+        }
+    }
+
+    // Proof for the watched leaf at `node_index` inside the tree with `n_nodes` leaves
+    fn proof(self: &Self, node_index: u128, n_nodes: u128) -> Proof {
+        (0..tree_height(n_nodes)).map(|level| {
+            if let Some(Some(sibling)) = self.siblings.get(level) {
+                return Some(*sibling);
+            }
+            match self.cursor {
+                // Right sibling with only some of its leaves inserted
+                Some((cursor_level, ref frontier)) if cursor_level == level => frontier.root_at_level(level),
+                // Right sibling with no leaves yet, node is hashed with itself
+                _ => {
+                    debug_assert!((node_index >> level) & 1 == 0, "Left siblings are always known; qed");
+                    None
+                },
+            }
+        }).collect()
+    }
+}
+
+// Keeps proofs of a set of watched leaves up to date, without keeping the whole tree
+// Every watched leaf only needs its siblings and the edge nodes of the sibling being filled
+pub struct WitnessTracker {
+    // Frontier of the whole tree
+    frontier: Frontier,
+    // Watched leaves by index
+    watched: BTreeMap<u128, Witness>,
+    // Indices of leaves to watch once they are inserted
+    pending_indices: BTreeSet<u128>,
+    // Hashes of leaves to watch once they are inserted
+    pending_values: HashSet<H256>,
+}
+
+impl WitnessTracker {
+    pub fn new() -> WitnessTracker {
+        WitnessTracker {
+            frontier: Frontier::default(),
+            watched: BTreeMap::new(),
+            pending_indices: BTreeSet::new(),
+            pending_values: HashSet::new(),
+        }
+    }
+
+    // Current root hash of the tree
+    pub fn root_hash(self: &Self) -> Option<H256> {
+        self.frontier.root_hash
+    }
+
+    // Number of leaf nodes
+    pub fn n_nodes(self: &Self) -> u128 {
+        self.frontier.n_nodes
+    }
+
+    // Start watching leaf at `node_index`
+    // Leaf can't be inserted yet, since its left siblings are only known at the time of insert
+    pub fn watch_index(self: &mut Self, node_index: u128) -> Result<(), &'static str> {
+        if node_index < self.frontier.n_nodes {
+            return Err("Leaf is already inserted!");
+        }
+        self.pending_indices.insert(node_index);
+        Ok(())
+    }
+
+    // Start watching the next leaf inserted with `value`
+    pub fn watch_value(self: &mut Self, value: Vec<u8>) {
+        self.watch_hash(BlakeTwo256::hash_of(&value));
+    }
+
+    // Start watching the next leaf `value_hash`, like the ones inserted with `insert_hash`
+    pub fn watch_hash(self: &mut Self, value_hash: H256) {
+        self.pending_values.insert(value_hash);
+    }
+
+    // Stop watching leaf at `node_index`
+    pub fn unwatch(self: &mut Self, node_index: u128) {
+        self.watched.remove(&node_index);
+        self.pending_indices.remove(&node_index);
+    }
+
+    // Indices of the watched leaves that are already inserted
+    pub fn watched_indices(self: &Self) -> Vec<u128> {
+        self.watched.keys().cloned().collect()
+    }
+
+    // Adds events emitted by the module, updating the proofs of all watched leaves
+    pub fn build_tree_from_events(self: &mut Self, events: Vec<H256>) {
+        for event_record in events.iter() {
+            self.insert(*event_record);
+        }
+    }
+
+    // Get proof for the watched leaf at `node_index` inside the current tree state
+    pub fn get_proof_for_index(self: &Self, node_index: u128) -> Result<Proof, &'static str> {
+        match self.watched.get(&node_index) {
+            Some(witness) => Ok(witness.proof(node_index, self.frontier.n_nodes)),
+            None => Err("Node is not watched!"),
+        }
+    }
+
+    // Get proof and index of the last watched leaf with `value` inside the current tree state
+    pub fn get_proof_for(self: &Self, value: Vec<u8>) -> Result<(u128, Proof), &'static str> {
+        self.get_proof_for_hash(BlakeTwo256::hash_of(&value))
+    }
+
+    // Get proof and index of the last watched leaf `value_hash` inside the current tree state
+    pub fn get_proof_for_hash(self: &Self, value_hash: H256) -> Result<(u128, Proof), &'static str> {
+        match self.watched.iter().rev().find(|(_, witness)| witness.leaf == value_hash) {
+            Some((node_index, witness)) => Ok((*node_index, witness.proof(*node_index, self.frontier.n_nodes))),
+            None => Err("Node is not watched!"),
+        }
+    }
+
+    // Insert a hash into the tree
+    fn insert(self: &mut Self, value_hash: H256) {
+        let new_index = self.frontier.n_nodes;
+        // New leaf belongs to one of the right siblings of every watched leaf
+        for (node_index, witness) in self.watched.iter_mut() {
+            witness.append(*node_index, new_index, value_hash);
+        }
+
+        // Leaf can be watched both by index and by value, so both are always removed
+        let is_watched = self.pending_indices.remove(&new_index) | self.pending_values.remove(&value_hash);
+        if is_watched {
+            // Left siblings of the new leaf are the edge nodes on levels where bit of its index is set
+            let siblings = self.frontier.edge_nodes.iter().enumerate().map(|(level, edge_node)| {
+                if (new_index >> level) & 1 == 1 { *edge_node } else { None }
+            }).collect();
+            self.watched.insert(new_index, Witness {
+                leaf: value_hash,
+                siblings,
+                cursor: None,
+            });
+        }
+
+        self.frontier.insert(value_hash);
+    }
+}
+
+// Number of levels above the leaves in a tree with `n_nodes` leaves
+fn tree_height(n_nodes: u128) -> usize {
+    if n_nodes <= 1 {
+        return 0;
+    }
+    (128 - (n_nodes - 1).leading_zeros()) as usize
+}
+
+// Calculating the successive number of 1 bits, starting from the right e.g.:
+// 0001 - 1
+// 0010 - 0
+// 0011 - 2
+// 1000 - 0
+fn count_bit_set_from_right(mut num: u128) -> usize {
+    let mut len = 0;
+    while (num & 1) > 0 {
+        num >>= 1;
+        len += 1;
+    }
+    len
+}
@@ -30,13 +30,14 @@ extern crate srml_balances as balances;
 extern crate srml_upgrade_key as upgrade_key;
 extern crate srml_aura as aura;
 extern crate substrate_consensus_aura_primitives as consensus_aura;
+extern crate merkle_tree;
 
 use rstd::prelude::*;
 #[cfg(feature = "std")]
 use primitives::bytes;
 use primitives::{Ed25519AuthorityId, OpaqueMetadata};
 use runtime_primitives::{
-	ApplyResult, transaction_validity::TransactionValidity, Ed25519Signature, generic,
+	ApplyResult, transaction_validity::{TransactionLongevity, TransactionValidity}, Ed25519Signature, generic,
 	traits::{self, BlakeTwo256, Block as BlockT, ProvideInherent},
 	BasicInherentData, CheckInherentError
 };
@@ -52,6 +53,8 @@ pub use runtime_primitives::BuildStorage;
 pub use consensus::Call as ConsensusCall;
 pub use timestamp::Call as TimestampCall;
 pub use balances::Call as BalancesCall;
+pub use merkle_tree::Call as MerkleTreeCall;
+pub use merkle_tree::FeeDestination;
 pub use runtime_primitives::{Permill, Perbill};
 pub use timestamp::BlockPeriod;
 pub use srml_support::{StorageValue, RuntimeMetadata};
@@ -94,6 +97,27 @@ pub mod opaque {
 	pub type SessionKey = Ed25519AuthorityId;
 }
 
+/// Runtime API of the merkle tree module.
+pub mod merkle_tree_api {
+	use super::*;
+
+	decl_runtime_apis! {
+		/// State of the on-chain merkle tree.
+		pub trait MerkleTreeApi {
+			/// Root hash of the tree, `None` until the first insert.
+			fn root_hash() -> Option<<Block as BlockT>::Hash>;
+			/// Number of leaves in the tree.
+			fn n_nodes() -> u128;
+			/// Hash of the leaf at `index`, `None` if there is no such leaf or it wasn't stored.
+			fn leaf_at(index: u128) -> Option<<Block as BlockT>::Hash>;
+			/// Indices `leaf` was inserted at, in order of insertion.
+			fn indices_of(leaf: <Block as BlockT>::Hash) -> Vec<u128>;
+			/// Proof for the leaf at `index` against the current root, `None` if leaves are not stored.
+			fn proof_at(index: u128) -> Option<Vec<Option<<Block as BlockT>::Hash>>>;
+		}
+	}
+}
+
 /// This runtime version.
 pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("substrate-merkle-tree"),
@@ -104,6 +128,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	apis: RUNTIME_API_VERSIONS,
 };
 
+/// Number of blocks an insert stays in the transaction pool. Pool can't check the limit of inserts
+/// per block, so inserts beyond it are dropped after this many blocks instead of piling up.
+pub const INSERT_LONGEVITY: TransactionLongevity = 16;
+
 /// The version infromation used to identify this runtime when compiled natively.
 #[cfg(feature = "std")]
 pub fn native_version() -> NativeVersion {
@@ -179,6 +207,21 @@ impl upgrade_key::Trait for Runtime {
 	type Event = Event;
 }
 
+impl merkle_tree::Trait for Runtime {
+	/// Leaves are made from raw bytes.
+	type Leaf = Vec<u8>;
+	/// Inserts update the tree right away, so their events keep the index of the extrinsic.
+	const AGGREGATE_INSERTS: bool = false;
+	/// Leaves are stored, so membership and proofs can be read through the runtime API.
+	const STORE_LEAVES: bool = true;
+	/// The same leaf can be inserted more than once.
+	const UNIQUE_LEAVES: bool = false;
+	/// The uniquitous event type.
+	type Event = Event;
+	/// The ubiquitous log type.
+	type Log = Log;
+}
+
 construct_runtime!(
 	pub enum Runtime with Log(InternalLog: DigestItem<Hash, Ed25519AuthorityId>) where
 		Block = Block,
@@ -191,13 +234,24 @@ construct_runtime!(
 		Aura: aura::{Module},
 		Balances: balances,
 		UpgradeKey: upgrade_key,
+		MerkleTree: merkle_tree::{Module, Call, Storage, Event<T>, Config<T>, Log()},
 	}
 );
 
+/// Root of the merkle tree committed to by block headers, read with `merkle_tree::root_from_header`.
+impl merkle_tree::RootDigestItem<Hash> for Log {
+	fn as_root_change(&self) -> Option<(Hash, u128)> {
+		match self.0 {
+			InternalLog::merkle_tree(ref log) => merkle_tree::RootDigestItem::as_root_change(log),
+			_ => None,
+		}
+	}
+}
+
 /// The type used as a helper for interpreting the sender of transactions.
 type Context = balances::ChainContext<Runtime>;
 /// The address format for describing accounts.
-type Address = balances::Address<Runtime>;
+pub type Address = balances::Address<Runtime>;
 /// Block header type as expected by this runtime.
 pub type Header = generic::Header<BlockNumber, BlakeTwo256, Log>;
 /// Block type as expected by this runtime.
@@ -276,7 +330,24 @@ impl_runtime_apis! {
 
 	impl runtime_api::TaggedTransactionQueue<Block> for Runtime {
 		fn validate_transaction(tx: <Block as BlockT>::Extrinsic) -> TransactionValidity {
-			Executive::validate_transaction(tx)
+			// Inserts of accounts over their limit for the era would fail, so they are kept out of the pool
+			// Limit of inserts per block can't be checked against the last block, it's left to block building,
+			// inserts that don't make it into a block within `INSERT_LONGEVITY` blocks leave the pool
+			let is_insert = match tx.function {
+				Call::MerkleTree(MerkleTreeCall::insert(..)) | Call::MerkleTree(MerkleTreeCall::insert_hash(..)) => true,
+				_ => false,
+			};
+			if let (true, Some((ref address, _, _, _))) = (is_insert, tx.signature.as_ref()) {
+				let who = traits::Lookup::lookup(&Context::default(), address.clone());
+				if who.map(|who| MerkleTree::can_insert(&who).is_err()).unwrap_or(false) {
+					return TransactionValidity::Invalid;
+				}
+			}
+			match Executive::validate_transaction(tx) {
+				TransactionValidity::Valid { priority, requires, provides, longevity } if is_insert =>
+					TransactionValidity::Valid { priority, requires, provides, longevity: rstd::cmp::min(longevity, INSERT_LONGEVITY) },
+				validity => validity,
+			}
 		}
 	}
 
@@ -285,4 +356,26 @@ impl_runtime_apis! {
 			Aura::slot_duration()
 		}
 	}
+
+	impl merkle_tree_api::MerkleTreeApi<Block> for Runtime {
+		fn root_hash() -> Option<<Block as BlockT>::Hash> {
+			MerkleTree::root_hash()
+		}
+
+		fn n_nodes() -> u128 {
+			MerkleTree::n_nodes()
+		}
+
+		fn leaf_at(index: u128) -> Option<<Block as BlockT>::Hash> {
+			MerkleTree::leaf_at(index)
+		}
+
+		fn indices_of(leaf: <Block as BlockT>::Hash) -> Vec<u128> {
+			MerkleTree::indices_of(leaf)
+		}
+
+		fn proof_at(index: u128) -> Option<Vec<Option<<Block as BlockT>::Hash>>> {
+			MerkleTree::proof_at(index)
+		}
+	}
 }
@@ -0,0 +1,92 @@
+#!/usr/bin/env bash
+# Starts a temporary dev node and checks the merkle RPC against it, first on the empty chain and
+# then after notarizing two files as Alice. Run after `cargo build --release`.
+
+set -e
+
+PROJECT_ROOT="$( cd "$( dirname "${BASH_SOURCE[0]}" )/.." >/dev/null && pwd )"
+NODE="$PROJECT_ROOT/target/release/substrate-merkle-tree"
+PORT=19933
+WORK_DIR=$(mktemp -d)
+
+"$NODE" --dev --tmp --rpc-port $PORT >/dev/null 2>&1 &
+NODE_PID=$!
+trap "kill $NODE_PID; rm -rf $WORK_DIR" EXIT
+
+call() {
+	curl -s -H "Content-Type: application/json" \
+		-d "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"$1\",\"params\":$2}" \
+		http://127.0.0.1:$PORT
+}
+
+expect() {
+	local response
+	response=$(call "$1" "$2")
+	if [[ "$response" != *"$3"* ]]; then
+		echo "$1 $2: expected $3, got $response"
+		exit 1
+	fi
+}
+
+# Wait for the node to import a few blocks
+for _ in $(seq 30); do
+	call merkle_leafCount "[]" | grep -q result && break
+	sleep 1
+done
+
+HASH="0x$(printf '11%.0s' $(seq 32))"
+
+# Nothing is inserted on a fresh dev chain
+expect merkle_root "[]" '"result":null'
+expect merkle_leafCount "[]" '"result":0'
+expect merkle_getLeaves "[0, 10]" '"result":[]'
+expect merkle_getProof "[0]" '"error"'
+expect merkle_getProof "[\"$HASH\"]" '"error"'
+expect merkle_verifyProof "[{\"leafIndex\":0,\"leafHash\":\"$HASH\",\"root\":\"$HASH\",\"proof\":[]}]" '"result":false'
+expect merkle_getReceipt "[0]" '"error"'
+expect merkle_verifyReceipt "[{\"leaf\":\"$HASH\",\"index\":0,\"root\":\"$HASH\",\"proof\":[],\"block\":{\"hash\":\"$HASH\",\"number\":1,\"extrinsicIndex\":1,\"account\":null},\"timestamp\":0}]" '"result":false'
+expect merkle_leafAt "[0]" '"result":null'
+expect merkle_indicesOf "[\"$HASH\"]" '"result":[]'
+expect merkle_getChainProof "[0]" '"error"'
+
+# Insert two leaves, notarize waits until both are included and indexed
+echo "first document" > "$WORK_DIR/a.txt"
+echo "second document" > "$WORK_DIR/b.txt"
+"$NODE" merkle notarize --seed Alice --rpc-url "http://127.0.0.1:$PORT" --receipts "$WORK_DIR/receipts" \
+	--timeout 60 "$WORK_DIR/a.txt" "$WORK_DIR/b.txt"
+
+# Value of the `"$2":` field in the JSON `$1`
+field() {
+	sed -n "s/.*\"$2\":\(\"[^\"]*\"\|[0-9]*\).*/\1/p" <<< "$1"
+}
+
+# Leaf hash of the receipt for the leaf at index `$1`
+receipt_leaf() {
+	local receipt
+	for file in "$WORK_DIR"/receipts/*.json; do
+		receipt=$(tr -d ' \n' < "$file")
+		if [[ "$(field "$receipt" leafIndex)" == "$1" ]]; then
+			field "$receipt" leafHash
+		fi
+	done
+}
+
+LEAF_A=$(receipt_leaf 0)
+LEAF_B=$(receipt_leaf 1)
+ROOT=$(field "$(call merkle_root "[]")" result)
+
+expect merkle_leafCount "[]" '"result":2'
+expect merkle_getLeaves "[0, 10]" "\"result\":[$LEAF_A,$LEAF_B]"
+expect merkle_getLeaves "[1, 10]" "\"result\":[$LEAF_B]"
+expect merkle_getProof "[0]" "\"leafHash\":$LEAF_A"
+expect merkle_getProof "[0]" "\"root\":$ROOT"
+expect merkle_getProof "[$LEAF_B]" '"leafIndex":1'
+expect merkle_getProof "[$LEAF_B]" "\"root\":$ROOT"
+
+# Proof returned by the node verifies, the same proof for another leaf doesn't
+PROOF=$(call merkle_getProof "[$LEAF_A]" | sed 's/.*"result":\({[^}]*}\).*/\1/')
+expect merkle_verifyProof "[$PROOF]" '"result":true'
+expect merkle_verifyProof "[${PROOF/$LEAF_A/\"$HASH\"}]" '"result":false'
+expect merkle_root "[]" "\"result\":$ROOT"
+
+echo "Merkle RPC tests passed"
@@ -1,6 +1,7 @@
 use primitives::{Ed25519AuthorityId, ed25519};
 use substrate_merkle_tree_runtime::{
 	AccountId, GenesisConfig, ConsensusConfig, TimestampConfig, BalancesConfig, UpgradeKeyConfig,
+	MerkleTreeConfig, FeeDestination,
 };
 use substrate_service;
 
@@ -97,5 +98,13 @@ fn testnet_genesis(initial_authorities: Vec<Ed25519AuthorityId>, endowed_account
 		upgrade_key: Some(UpgradeKeyConfig {
 			key: upgrade_key,
 		}),
+		merkle_tree: Some(MerkleTreeConfig {
+			leaf_fee: 10,
+			byte_fee: 1,
+			fee_recipient: FeeDestination::Burn,
+			max_inserts_per_block: 100,
+			max_inserts_per_account_per_era: 1000,
+			insert_era_length: 720,		// An hour of blocks.
+		}),
 	}
 }
@@ -6,7 +6,10 @@ pub use substrate_cli::{VersionInfo, IntoExit, error};
 use substrate_cli::{Action, informant, parse_matches, execute_default, CoreParams};
 use substrate_service::{ServiceFactory, Roles as ServiceRoles};
 use chain_spec;
+use merkle::{self, MerkleParams};
+use std::ffi::OsString;
 use std::ops::Deref;
+use std::path::Path;
 use structopt::StructOpt;
 
 /// Extend params for Node
@@ -27,9 +30,21 @@ pub struct NodeParams {
 /// Parse command line arguments into service configuration.
 pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()> where
 	I: IntoIterator<Item = T>,
-	T: Into<std::ffi::OsString> + Clone,
+	T: Into<OsString> + Clone,
 	E: IntoExit,
 {
+	let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+	// `merkle` works on the database of a stopped node, without the node's own params
+	if args.get(1).map_or(false, |arg| arg == "merkle") {
+		let matches = match MerkleParams::clap()
+			.author(version.author)
+			.get_matches_from_safe(&args[1..]) {
+				Ok(m) => m,
+				Err(e) => e.exit(),
+			};
+		return merkle::run(MerkleParams::from_clap(&matches), &version);
+	}
+
 	let full_version = substrate_service::config::full_version_from_strs(
 		version.version,
 		version.commit
@@ -45,9 +60,11 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 			Err(e) => e.exit(),
 		};
 
-	let (spec, config) = parse_matches::<service::Factory, _>(
+	let (spec, mut config) = parse_matches::<service::Factory, _>(
 		load_spec, version, "substrate-node", &matches
 	)?;
+	// Merkle index lives next to the chain database, so purging the chain purges it as well
+	config.custom.merkle_index_dir = Path::new(&config.database_path).join("merkle").to_str().map(|dir| dir.to_string());
 
 	match execute_default::<service::Factory, _>(spec, exit, &matches, &config)? {
 		Action::ExecutedInternally => (),
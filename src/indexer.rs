@@ -0,0 +1,699 @@
+//! Merkle tree index of a full node. Follows block imports and keeps a `MerkleTreeClient` built from
+//! the `Insert` events of the best chain, so proofs can be served without replaying the chain.
+//! Every applied insert is sent to subscribers, and sent again once its block is finalized.
+//! For every leaf, the block, extrinsic and account that inserted it are recorded as well, so
+//! proofs can be handed out as inclusion receipts.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+use balances::address::Address;
+use client::BlockchainEvents;
+use codec::{Decode, Encode};
+use futures::{future, Future, Stream, sync::mpsc};
+use merkle_proof;
+use merkle_tree::{ClientHasher, InsertEvent, MerkleTreeClient, RawEvent, RollbackTarget};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+use primitives::{storage::StorageKey, twox_128, H256};
+use runtime_primitives::generic::BlockId;
+use runtime_primitives::traits::Header as HeaderT;
+use serde_json;
+use service::Factory;
+use substrate_merkle_tree_runtime::{opaque, AccountId, Event, Runtime, UncheckedExtrinsic};
+use substrate_service::FullClient;
+use system::{EventRecord, Phase};
+
+/// Number of blocks between two checkpoints of the index.
+const CHECKPOINT_INTERVAL: u64 = 100;
+/// File next to the checkpoint holding the hash of the block it was saved at.
+const CHECKPOINT_BLOCK_FILE: &str = "checkpoint_block";
+/// File next to the checkpoint holding the origins of its leaves.
+const LEAF_ORIGINS_FILE: &str = "leaf_origins";
+/// Number of accounts in a single `Balances EnumSet` entry, same as in `balances`.
+const ENUM_SET_SIZE: u32 = 64;
+/// Number of blocks below the best block whose inserts are still kept until they are finalized.
+const MAX_UNFINALIZED_BLOCKS: u64 = 4096;
+
+/// Leaf inserted by a block of the best chain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MerkleInsert {
+	/// Hash of the block the leaf was inserted in.
+	pub block_hash: H256,
+	/// Number of the block the leaf was inserted in.
+	pub block_number: u64,
+	/// Zero based index of the leaf.
+	pub leaf_index: u64,
+	/// Hash of the leaf.
+	pub leaf_hash: H256,
+	/// Root hash of the tree right after the leaf was inserted.
+	pub new_root: H256,
+	/// Inserts of blocks that are not finalized can be reverted by a reorg, in which case
+	/// inserts of the new best chain follow, starting from the first reverted leaf index.
+	pub finalized: bool,
+}
+
+/// Leaf referred to either by its index or by its hash.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LeafRef {
+	/// Zero based index of the leaf.
+	Index(u64),
+	/// Hash of the leaf, as emitted in the `Insert` event.
+	Hash(H256),
+}
+
+/// Proof that a leaf is part of the tree state with `root`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MerkleProof {
+	/// Zero based index of the leaf.
+	pub leaf_index: u64,
+	/// Hash of the leaf.
+	pub leaf_hash: H256,
+	/// Root hash of the tree state the proof is for.
+	pub root: H256,
+	/// Sibling on every level, `null` where the node is hashed with itself.
+	pub proof: Vec<Option<H256>>,
+}
+
+impl MerkleProof {
+	/// Check the proof the same way the runtime does, while the tree has `n_nodes` leaves.
+	pub fn verify(&self, n_nodes: u128) -> bool {
+		merkle_proof::verify_proof::<ClientHasher>(&self.proof, self.leaf_hash, self.leaf_index as u128, n_nodes, self.root).is_ok()
+	}
+}
+
+/// Block and extrinsic a leaf was inserted by.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InclusionBlock {
+	/// Hash of the block.
+	pub hash: H256,
+	/// Number of the block.
+	pub number: u64,
+	/// Index of the inserting extrinsic inside the block, `None` if the leaf wasn't inserted by an extrinsic.
+	pub extrinsic_index: Option<u32>,
+	/// Account that signed the inserting extrinsic, `None` for unsigned extrinsics.
+	pub account: Option<AccountId>,
+}
+
+/// Where a leaf comes from, recorded for every leaf of the index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeafOrigin {
+	/// Block and extrinsic that inserted the leaf.
+	pub block: InclusionBlock,
+	/// Timestamp of the block, as set by the timestamp module.
+	pub timestamp: u64,
+}
+
+/// Proof of a leaf together with the block that inserted it, verifiable later against the chain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InclusionReceipt {
+	/// Hash of the leaf.
+	pub leaf: H256,
+	/// Zero based index of the leaf.
+	pub index: u64,
+	/// Root hash of the tree state the proof is for.
+	pub root: H256,
+	/// Sibling on every level, `null` where the node is hashed with itself.
+	pub proof: Vec<Option<H256>>,
+	/// Block and extrinsic that inserted the leaf.
+	pub block: InclusionBlock,
+	/// Timestamp of the block that inserted the leaf.
+	pub timestamp: u64,
+}
+
+// Leaf inserted by a block, with the extrinsic that inserted it
+struct IndexedLeaf {
+	event: InsertEvent,
+	extrinsic_index: Option<u32>,
+	account: Option<AccountId>,
+}
+
+// Block whose inserts are applied to the tree
+struct IndexedBlock {
+	hash: H256,
+	parent_hash: H256,
+	number: u64,
+	timestamp: u64,
+	leaves: Vec<IndexedLeaf>,
+}
+
+// Applied block that is not finalized yet
+struct PendingBlock {
+	parent_hash: H256,
+	number: u64,
+	inserts: Vec<MerkleInsert>,
+}
+
+/// Merkle tree of the best chain, shared between the indexer task and RPC.
+pub struct MerkleIndex {
+	tree: RwLock<MerkleTreeClient>,
+	// Origin of every leaf of the tree, by leaf index. Locked after `tree` when both are needed
+	origins: RwLock<Vec<LeafOrigin>>,
+	// Folder of the checkpoint, the index is only kept in memory when `None`
+	checkpoint_dir: Option<String>,
+	// Applied blocks waiting for finality, by hash
+	pending: Mutex<HashMap<H256, PendingBlock>>,
+	// Subscribers to every insert
+	leaf_sinks: Mutex<Vec<mpsc::UnboundedSender<MerkleInsert>>>,
+	// Subscribers to the last insert of every block
+	root_sinks: Mutex<Vec<mpsc::UnboundedSender<MerkleInsert>>>,
+}
+
+impl MerkleIndex {
+	/// Create an empty index, saving its checkpoints inside `checkpoint_dir`.
+	pub fn new(checkpoint_dir: Option<String>) -> MerkleIndex {
+		if let Some(ref dir) = checkpoint_dir {
+			if let Err(e) = fs::create_dir_all(dir) {
+				warn!(target: "merkle", "Could not create merkle index folder {}: {}", dir, e);
+			}
+		}
+		MerkleIndex {
+			tree: RwLock::new(MerkleIndex::new_tree(MerkleTreeClient::with_snapshot_dir(checkpoint_dir.clone()))),
+			origins: RwLock::new(vec![]),
+			checkpoint_dir,
+			pending: Mutex::new(HashMap::new()),
+			leaf_sinks: Mutex::new(vec![]),
+			root_sinks: Mutex::new(vec![]),
+		}
+	}
+
+	/// Tree as of the last indexed block. Indexing waits while the guard is held.
+	pub fn tree(&self) -> RwLockReadGuard<MerkleTreeClient> {
+		self.tree.read()
+	}
+
+	/// Proof for the leaf inside the tree state with `root`, or inside the newest state.
+	/// Leaf inserted more than once is found by hash at its last index.
+	pub fn proof(&self, leaf: LeafRef, root: Option<H256>) -> Result<MerkleProof, String> {
+		proof_in(&self.tree.read(), leaf, root)
+	}
+
+	/// Block, extrinsic and account that inserted the leaf at `leaf_index`.
+	pub fn origin(&self, leaf_index: u64) -> Option<LeafOrigin> {
+		self.origins.read().get(leaf_index as usize).cloned()
+	}
+
+	/// Proof for the leaf like `proof`, together with the block that inserted the leaf.
+	pub fn receipt(&self, leaf: LeafRef, root: Option<H256>) -> Result<InclusionReceipt, String> {
+		let tree = self.tree.read();
+		let origins = self.origins.read();
+		let proof = proof_in(&tree, leaf, root)?;
+		let origin = origins.get(proof.leaf_index as usize).cloned()
+			.ok_or_else(|| format!("Origin of leaf {} is not known", proof.leaf_index))?;
+
+		Ok(InclusionReceipt {
+			leaf: proof.leaf_hash,
+			index: proof.leaf_index,
+			root: proof.root,
+			proof: proof.proof,
+			block: origin.block,
+			timestamp: origin.timestamp,
+		})
+	}
+
+	/// Check that the receipt's root is a tree state of the best chain, that the proof is valid for it
+	/// and that the leaf was inserted by the receipt's block.
+	pub fn verify_receipt(&self, receipt: &InclusionReceipt) -> Result<(), String> {
+		let tree = self.tree.read();
+		let origins = self.origins.read();
+		let n_nodes = tree.get_n_nodes_at(&receipt.root)
+			.ok_or_else(|| format!("Root {} is not a tree state of the best chain", receipt.root))?;
+		if receipt.index as u128 >= n_nodes {
+			return Err(format!("Leaf {} is not part of the tree state with root {}", receipt.index, receipt.root));
+		}
+		merkle_proof::verify_proof::<ClientHasher>(&receipt.proof, receipt.leaf, receipt.index as u128, tree.n_nodes, receipt.root)
+			.map_err(|e| e.as_str().to_string())?;
+		match origins.get(receipt.index as usize) {
+			Some(origin) if origin.block == receipt.block && origin.timestamp == receipt.timestamp => Ok(()),
+			Some(_) => Err(format!("Leaf {} was inserted by a different block or extrinsic", receipt.index)),
+			None => Err(format!("Origin of leaf {} is not known", receipt.index)),
+		}
+	}
+
+	/// Stream of every insert applied from now on.
+	pub fn leaf_notification_stream(&self) -> mpsc::UnboundedReceiver<MerkleInsert> {
+		let (sink, stream) = mpsc::unbounded();
+		self.leaf_sinks.lock().push(sink);
+		stream
+	}
+
+	/// Stream of the last insert of every block applied from now on, carrying the new root of the block.
+	pub fn root_notification_stream(&self) -> mpsc::UnboundedReceiver<MerkleInsert> {
+		let (sink, stream) = mpsc::unbounded();
+		self.root_sinks.lock().push(sink);
+		stream
+	}
+
+	// Apply leaves inserted by block `hash` on top of its parent, which has to be applied already
+	#[cfg(test)]
+	pub fn import_leaves(&self, hash: H256, parent_hash: H256, number: u64, leaves: Vec<H256>) -> Result<(), String> {
+		let common_ancestor = if number == 1 { RollbackTarget::NNodes(0) } else { RollbackTarget::Block(parent_hash) };
+		// Events carry the index and the root of every leaf, so they are computed on top of the parent
+		let mut parent_tree = MerkleTreeClient::with_snapshot_dir(None);
+		{
+			let tree = self.tree.read();
+			let n_nodes = match common_ancestor {
+				RollbackTarget::Block(parent_hash) => tree.get_n_nodes_at_block(&parent_hash).ok_or("Parent block is not applied")?,
+				RollbackTarget::NNodes(n_nodes) => n_nodes,
+			};
+			parent_tree.append_leaves(tree.leaves[..n_nodes as usize].to_vec());
+		}
+		let leaves = leaves.into_iter().enumerate().map(|(i, hash)| {
+			parent_tree.append_leaves(vec![hash]);
+			IndexedLeaf {
+				event: (parent_tree.n_nodes - 1, hash, parent_tree.root_hash.expect("Leaf was just inserted; qed")),
+				extrinsic_index: Some(i as u32),
+				account: None,
+			}
+		}).collect();
+		// Block numbers double as timestamps
+		let block = IndexedBlock { hash, parent_hash, number, timestamp: number, leaves };
+		self.apply_blocks(common_ancestor, vec![block])
+	}
+
+	/// Continue from the last checkpoint and index every block up to the best block of `client`.
+	/// Returns the hash of the best block.
+	pub fn sync(&self, client: &FullClient<Factory>) -> Result<H256, String> {
+		if let Err(e) = self.resume(client) {
+			warn!(target: "merkle", "Could not resume merkle index, indexing from genesis: {}", e);
+		}
+		let best_hash = client.info().map_err(|e| e.to_string())?.chain.best_hash;
+		self.import_block(client, best_hash)?;
+		Ok(best_hash)
+	}
+
+	// Tree checking events the same way the runtime checks inserts
+	fn new_tree(mut tree: MerkleTreeClient) -> MerkleTreeClient {
+		tree.unique_leaves = <Runtime as ::merkle_tree::Trait>::UNIQUE_LEAVES;
+		tree
+	}
+
+	// Continue from the last checkpoint, if the chain still contains it
+	fn resume(&self, client: &FullClient<Factory>) -> Result<(), String> {
+		let dir = match self.checkpoint_dir {
+			Some(ref dir) => dir.clone(),
+			None => return Ok(()),
+		};
+		let block_hash = match read_checkpoint_block(&dir) {
+			Some(block_hash) => block_hash,
+			None => return Ok(()),
+		};
+		let origins = read_leaf_origins(&dir)?;
+		let mut tree = MerkleIndex::new_tree(MerkleTreeClient::from_checkpoint(dir)?);
+		// Checkpoint and its block are written one after the other, so they are checked against the chain
+		let root_hash: Option<H256> = storage_at(client, block_hash, b"MerkleTree RootHash")?;
+		if tree.root_hash != root_hash {
+			return Err(format!("Checkpoint doesn't match block {}", block_hash));
+		}
+		if origins.len() as u128 != tree.n_nodes {
+			return Err("Leaf origins don't match the checkpoint".into());
+		}
+		// Block is known to the tree, so the following blocks are applied on top of it
+		tree.build_tree_from_block(block_hash, vec![]).map_err(|e| e.as_str())?;
+		info!(target: "merkle", "Resuming merkle index with {} leaves from block {}", tree.n_nodes, block_hash);
+		let mut current_tree = self.tree.write();
+		*current_tree = tree;
+		*self.origins.write() = origins;
+		Ok(())
+	}
+
+	// Apply the inserts of new best block `block_hash`
+	// Blocks of a different fork are rolled back first, blocks that were not applied yet are applied in order
+	fn import_block(&self, client: &FullClient<Factory>, block_hash: H256) -> Result<(), String> {
+		let mut route = vec![];
+		let common_ancestor = {
+			let tree = self.tree.read();
+			let mut current = block_hash;
+			loop {
+				if tree.has_block(&current) {
+					break RollbackTarget::Block(current);
+				}
+				let header = client.header(&BlockId::Hash(current))
+					.map_err(|e| e.to_string())?
+					.ok_or_else(|| format!("Header of block {} not found", current))?;
+				// Genesis doesn't have any inserts
+				if *header.number() == 0 {
+					break RollbackTarget::NNodes(0);
+				}
+				route.push((current, *header.parent_hash(), *header.number()));
+				current = *header.parent_hash();
+			}
+		};
+
+		let mut blocks = vec![];
+		for (hash, parent_hash, number) in route.into_iter().rev() {
+			let leaves = inserted_leaves(client, hash)?;
+			let timestamp = storage_at(client, hash, b"Timestamp Now")?.unwrap_or_default();
+			blocks.push(IndexedBlock { hash, parent_hash, number, timestamp, leaves });
+		}
+		self.apply_blocks(common_ancestor, blocks)
+	}
+
+	// Roll back to `common_ancestor` and apply `blocks` in order, notifying subscribers of their inserts
+	fn apply_blocks(&self, common_ancestor: RollbackTarget, blocks: Vec<IndexedBlock>) -> Result<(), String> {
+		let mut notifications = vec![];
+		let mut result = Ok(());
+		{
+			let mut tree = self.tree.write();
+			let mut origins = self.origins.write();
+			let mut pending = self.pending.lock();
+			tree.rollback_to(common_ancestor)?;
+			origins.truncate(tree.n_nodes as usize);
+			for block in blocks {
+				if let Err(e) = tree.build_tree_from_block(block.hash, block.leaves.iter().map(|leaf| leaf.event).collect()) {
+					// Following blocks can't be applied either, the ones applied so far are still notified
+					result = Err(format!("Merkle index diverged from the chain at block {}: {}", block.hash, e.as_str()));
+					break;
+				}
+				let inserts: Vec<MerkleInsert> = block.leaves.iter().map(|leaf| {
+					let (leaf_index, leaf_hash, new_root) = leaf.event;
+					MerkleInsert {
+						block_hash: block.hash,
+						block_number: block.number,
+						leaf_index: leaf_index as u64,
+						leaf_hash,
+						new_root,
+						finalized: false,
+					}
+				}).collect();
+				origins.extend(block.leaves.into_iter().map(|leaf| LeafOrigin {
+					block: InclusionBlock {
+						hash: block.hash,
+						number: block.number,
+						extrinsic_index: leaf.extrinsic_index,
+						account: leaf.account,
+					},
+					timestamp: block.timestamp,
+				}));
+				notifications.push(inserts.clone());
+
+				// Blocks that stay unfinalized for too long are not notified again, so chains without
+				// finality don't keep every insert in memory
+				pending.retain(|_, pending_block| pending_block.number + MAX_UNFINALIZED_BLOCKS > block.number);
+				pending.insert(block.hash, PendingBlock {
+					parent_hash: block.parent_hash,
+					number: block.number,
+					inserts,
+				});
+			}
+		}
+		for inserts in notifications {
+			self.notify(inserts);
+		}
+		result
+	}
+
+	// Notify inserts of finalized block `block_hash` and of its ancestors that were not finalized yet
+	pub(crate) fn finalize_block(&self, block_hash: H256) {
+		let mut finalized = vec![];
+		{
+			let mut pending = self.pending.lock();
+			let number = match pending.get(&block_hash) {
+				Some(block) => block.number,
+				None => return,
+			};
+			let mut current = block_hash;
+			while let Some(block) = pending.remove(&current) {
+				current = block.parent_hash;
+				finalized.push(block.inserts);
+			}
+			// Blocks of other forks can't be finalized anymore
+			pending.retain(|_, block| block.number > number);
+		}
+		for inserts in finalized.into_iter().rev() {
+			self.notify(inserts.into_iter().map(|insert| MerkleInsert { finalized: true, ..insert }).collect());
+		}
+	}
+
+	// Send inserts of a single block to subscribers
+	fn notify(&self, inserts: Vec<MerkleInsert>) {
+		if let Some(last) = inserts.last() {
+			self.root_sinks.lock().retain(|sink| sink.unbounded_send(last.clone()).is_ok());
+		}
+		let mut leaf_sinks = self.leaf_sinks.lock();
+		for insert in inserts {
+			leaf_sinks.retain(|sink| sink.unbounded_send(insert.clone()).is_ok());
+		}
+	}
+
+	/// Save the tree together with the block it was indexed at.
+	pub fn save_checkpoint(&self, block_hash: H256) -> Result<(), String> {
+		let dir = match self.checkpoint_dir {
+			Some(ref dir) => dir,
+			None => return Ok(()),
+		};
+		let tree = self.tree.read();
+		tree.save_checkpoint()?;
+		let origins = serde_json::to_string(&*self.origins.read()).map_err(|e| e.to_string())?;
+		File::create(Path::new(dir).join(LEAF_ORIGINS_FILE))
+			.and_then(|mut file| file.write_all(origins.as_bytes()))
+			.map_err(|e| e.to_string())?;
+		let mut file = File::create(Path::new(dir).join(CHECKPOINT_BLOCK_FILE)).map_err(|e| e.to_string())?;
+		file.write_all(block_hash.as_ref()).map_err(|e| e.to_string())
+	}
+}
+
+/// Index the best chain of `client` into `index` until `on_exit` resolves.
+pub fn start<E>(
+	client: Arc<FullClient<Factory>>,
+	index: Arc<MerkleIndex>,
+	on_exit: E,
+) -> impl Future<Item = (), Error = ()> where
+	E: Future<Item = (), Error = ()>,
+{
+	// Catching up can replay a long chain, so it runs in the task instead of while the service starts
+	// Streams are subscribed right away, blocks imported in the meantime are indexed after it
+	let sync_client = client.clone();
+	let sync_index = index.clone();
+	let catch_up = future::lazy(move || {
+		if let Err(e) = sync_index.sync(&sync_client) {
+			warn!(target: "merkle", "Could not catch up with the best block: {}", e);
+		}
+		Ok(())
+	});
+
+	let finality_index = index.clone();
+	let finalized = client.finality_notification_stream()
+		.for_each(move |notification| {
+			finality_index.finalize_block(notification.hash);
+			Ok(())
+		});
+
+	let blocks = client.import_notification_stream()
+		.filter(|notification| notification.is_new_best)
+		.for_each(move |notification| {
+			if let Err(e) = index.import_block(&client, notification.hash) {
+				// Next best block walks back to the last indexed block, so nothing is skipped
+				warn!(target: "merkle", "Could not index block {}: {}", notification.hash, e);
+				return Ok(());
+			}
+			if *notification.header.number() % CHECKPOINT_INTERVAL == 0 {
+				if let Err(e) = index.save_checkpoint(notification.hash) {
+					warn!(target: "merkle", "Could not save merkle index checkpoint: {}", e);
+				}
+			}
+			Ok(())
+		});
+
+	catch_up.and_then(|_| blocks.join(finalized)).map(|_| ()).select(on_exit).then(|_| Ok(()))
+}
+
+// Proof for the leaf inside the tree state with `root` of `tree`, or inside its newest state
+fn proof_in(tree: &MerkleTreeClient, leaf: LeafRef, root: Option<H256>) -> Result<MerkleProof, String> {
+	let root = match root {
+		Some(root) => root,
+		None => tree.root_hash.ok_or("Tree is empty")?,
+	};
+	let leaf_index = match leaf {
+		LeafRef::Index(index) => index as u128,
+		LeafRef::Hash(hash) => *tree.node_indices.get(&hash).ok_or_else(|| format!("Leaf {} was never inserted", hash))?,
+	};
+	let leaf_hash = *tree.leaves.get(leaf_index as usize).ok_or_else(|| format!("Leaf {} was never inserted", leaf_index))?;
+	let proof = tree.get_proof_for_index(leaf_index, root)?;
+
+	Ok(MerkleProof {
+		leaf_index: leaf_index as u64,
+		leaf_hash,
+		root,
+		proof,
+	})
+}
+
+// Leaves inserted by block `block_hash`, in order of insertion, with the extrinsics that inserted them
+fn inserted_leaves(client: &FullClient<Factory>, block_hash: H256) -> Result<Vec<IndexedLeaf>, String> {
+	let events: Vec<EventRecord<Event>> = storage_at(client, block_hash, b"System Events")?.unwrap_or_default();
+	let inserts: Vec<(InsertEvent, Option<u32>)> = events.into_iter().filter_map(|record| match record.event {
+		Event::merkle_tree(RawEvent::Insert(leaf_index, leaf_hash, new_root)) => {
+			let extrinsic_index = match record.phase {
+				Phase::ApplyExtrinsic(extrinsic_index) => Some(extrinsic_index),
+				Phase::Finalization => None,
+			};
+			Some(((leaf_index, leaf_hash, new_root), extrinsic_index))
+		},
+		_ => None,
+	}).collect();
+	if inserts.is_empty() {
+		return Ok(vec![]);
+	}
+
+	let extrinsics = client.body(&BlockId::Hash(block_hash))
+		.map_err(|e| e.to_string())?
+		.ok_or_else(|| format!("Body of block {} not found", block_hash))?;
+	inserts.into_iter().map(|(event, extrinsic_index)| {
+		let account = match extrinsic_index.and_then(|index| extrinsics.get(index as usize)) {
+			Some(extrinsic) => extrinsic_signer(client, block_hash, extrinsic)?,
+			None => None,
+		};
+		Ok(IndexedLeaf { event, extrinsic_index, account })
+	}).collect()
+}
+
+// Account that signed `extrinsic` of block `block_hash`, `None` for unsigned extrinsics
+fn extrinsic_signer(client: &FullClient<Factory>, block_hash: H256, extrinsic: &opaque::UncheckedExtrinsic) -> Result<Option<AccountId>, String> {
+	let extrinsic = UncheckedExtrinsic::decode(&mut &extrinsic.encode()[..])
+		.ok_or_else(|| format!("Could not decode extrinsic of block {}", block_hash))?;
+	match extrinsic.signature {
+		Some((Address::Id(account), _, _, _)) => Ok(Some(account)),
+		// Indices are resolved the same way as by `balances`
+		Some((Address::Index(index), _, _, _)) => {
+			let mut key = b"Balances EnumSet".to_vec();
+			(index / ENUM_SET_SIZE).encode_to(&mut key);
+			let accounts: Vec<AccountId> = storage_at(client, block_hash, &key)?.unwrap_or_default();
+			Ok(accounts.get((index % ENUM_SET_SIZE) as usize).cloned())
+		},
+		None => Ok(None),
+	}
+}
+
+// Decoded value of storage item `key` in the state of block `block_hash`
+fn storage_at<T: Decode>(client: &FullClient<Factory>, block_hash: H256, key: &[u8]) -> Result<Option<T>, String> {
+	let key = StorageKey(twox_128(key).to_vec());
+	match client.storage(&BlockId::Hash(block_hash), &key).map_err(|e| e.to_string())? {
+		Some(data) => T::decode(&mut &data.0[..])
+			.map(Some)
+			.ok_or_else(|| format!("Could not decode storage of block {}", block_hash)),
+		None => Ok(None),
+	}
+}
+
+// Origins of the leaves saved with the last checkpoint inside `dir`
+fn read_leaf_origins(dir: &str) -> Result<Vec<LeafOrigin>, String> {
+	let file = File::open(Path::new(dir).join(LEAF_ORIGINS_FILE)).map_err(|e| e.to_string())?;
+	serde_json::from_reader(file).map_err(|e| e.to_string())
+}
+
+// Block the last checkpoint inside `dir` was saved at
+fn read_checkpoint_block(dir: &str) -> Option<H256> {
+	let mut file = File::open(Path::new(dir).join(CHECKPOINT_BLOCK_FILE)).ok()?;
+	let mut bytes = [0u8; 32];
+	file.read_exact(&mut bytes).ok()?;
+	Some(H256::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn block(number: u8) -> H256 {
+		H256::from([number; 32])
+	}
+
+	fn leaf(value: &str) -> H256 {
+		ClientHasher::hash_leaf(value.as_bytes())
+	}
+
+	// Every notification sent until the index is dropped
+	fn collect(stream: mpsc::UnboundedReceiver<MerkleInsert>) -> Vec<MerkleInsert> {
+		stream.collect().wait().unwrap()
+	}
+
+	#[test]
+	fn should_notify_every_insert_and_root_of_every_block() {
+		let index = MerkleIndex::new(None);
+		let leaves = index.leaf_notification_stream();
+		let roots = index.root_notification_stream();
+
+		index.import_leaves(block(1), block(0), 1, vec![leaf("a"), leaf("b")]).unwrap();
+		index.import_leaves(block(2), block(1), 2, vec![]).unwrap();
+		index.import_leaves(block(3), block(2), 3, vec![leaf("c")]).unwrap();
+		let tree_roots: Vec<H256> = (1..4).map(|n_nodes| index.tree().get_root_at(n_nodes).unwrap()).collect();
+		drop(index);
+
+		let leaves = collect(leaves);
+		assert_eq!(leaves.len(), 3);
+		for (i, insert) in leaves.iter().enumerate() {
+			assert_eq!(insert.leaf_index, i as u64);
+			assert_eq!(insert.new_root, tree_roots[i]);
+			assert_eq!(insert.finalized, false);
+		}
+		assert_eq!(leaves[1].block_hash, block(1));
+		assert_eq!(leaves[2].block_number, 3);
+		assert_eq!(leaves[2].leaf_hash, leaf("c"));
+
+		// Block without inserts doesn't change the root
+		assert_eq!(collect(roots), vec![leaves[1].clone(), leaves[2].clone()]);
+	}
+
+	#[test]
+	fn should_notify_inserts_of_new_fork_from_the_reverted_index() {
+		let index = MerkleIndex::new(None);
+		let leaves = index.leaf_notification_stream();
+
+		index.import_leaves(block(1), block(0), 1, vec![leaf("a")]).unwrap();
+		index.import_leaves(block(2), block(1), 2, vec![leaf("b")]).unwrap();
+		// Block 12 replaces block 2
+		index.import_leaves(block(12), block(1), 2, vec![leaf("c"), leaf("d")]).unwrap();
+		assert_eq!(index.tree().n_nodes, 3);
+		drop(index);
+
+		let indices: Vec<(H256, u64)> = collect(leaves).iter().map(|insert| (insert.block_hash, insert.leaf_index)).collect();
+		assert_eq!(indices, vec![(block(1), 0), (block(2), 1), (block(12), 1), (block(12), 2)]);
+	}
+
+	#[test]
+	fn should_record_origins_of_the_new_fork() {
+		let index = MerkleIndex::new(None);
+		index.import_leaves(block(1), block(0), 1, vec![leaf("a")]).unwrap();
+		index.import_leaves(block(2), block(1), 2, vec![leaf("b")]).unwrap();
+		// Block 12 replaces block 2
+		index.import_leaves(block(12), block(1), 2, vec![leaf("c"), leaf("d")]).unwrap();
+
+		let origins: Vec<(H256, Option<u32>)> = (0..4).filter_map(|i| index.origin(i))
+			.map(|origin| (origin.block.hash, origin.block.extrinsic_index))
+			.collect();
+		assert_eq!(origins, vec![(block(1), Some(0)), (block(12), Some(0)), (block(12), Some(1))]);
+
+		let receipt = index.receipt(LeafRef::Index(2), None).unwrap();
+		assert_eq!(receipt.leaf, leaf("d"));
+		assert_eq!(receipt.block, index.origin(2).unwrap().block);
+		assert!(index.verify_receipt(&receipt).is_ok());
+	}
+
+	#[test]
+	fn should_notify_inserts_again_once_finalized() {
+		let index = MerkleIndex::new(None);
+		index.import_leaves(block(1), block(0), 1, vec![leaf("a")]).unwrap();
+		index.import_leaves(block(2), block(1), 2, vec![leaf("b")]).unwrap();
+		index.import_leaves(block(12), block(1), 2, vec![leaf("c")]).unwrap();
+		index.import_leaves(block(13), block(12), 3, vec![leaf("d")]).unwrap();
+		let leaves = index.leaf_notification_stream();
+
+		// Finalizing block 12 finalizes block 1 as well, block 2 can't be finalized anymore
+		index.finalize_block(block(12));
+		index.finalize_block(block(2));
+		index.finalize_block(block(12));
+		index.finalize_block(block(13));
+		drop(index);
+
+		let finalized: Vec<(H256, u64, bool)> = collect(leaves).iter()
+			.map(|insert| (insert.block_hash, insert.leaf_index, insert.finalized))
+			.collect();
+		assert_eq!(finalized, vec![(block(1), 0, true), (block(12), 1, true), (block(13), 2, true)]);
+	}
+}
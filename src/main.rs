@@ -21,15 +21,38 @@ extern crate substrate_transaction_pool as transaction_pool;
 extern crate substrate_basic_authorship as basic_authorship;
 #[macro_use]
 extern crate substrate_service;
+extern crate substrate_rpc_servers;
 extern crate substrate_merkle_tree_runtime;
 #[macro_use]
 extern crate structopt;
 extern crate node_executor;
 extern crate sr_primitives as runtime_primitives;
+extern crate srml_system as system;
+extern crate srml_balances as balances;
+extern crate parity_codec as codec;
+extern crate parking_lot;
+extern crate merkle_tree;
+extern crate merkle_proof;
+extern crate jsonrpc_core;
+extern crate jsonrpc_pubsub;
+#[macro_use]
+extern crate jsonrpc_macros;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate app_dirs;
+extern crate hyper;
+extern crate substrate_keystore;
 
 mod chain_spec;
 mod service;
 mod cli;
+mod indexer;
+mod rpc;
+mod merkle;
+mod notarize;
 
 pub use substrate_cli::{VersionInfo, IntoExit, error};
 
@@ -0,0 +1,222 @@
+//! `merkle` subcommands. Except for `notarize`, they open the database of a stopped node, so
+//! operators can work with the merkle tree without running RPC.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use app_dirs::{AppDataType, AppInfo};
+use chain_spec;
+use indexer::{LeafRef, MerkleIndex, MerkleProof};
+use merkle_tree::ClientHasher;
+use notarize::{self, NotarizeParams};
+use primitives::H256;
+use rpc::MerkleChain;
+use serde_json;
+use service::Factory;
+use substrate_cli::{error, VersionInfo};
+use substrate_service::{self, FactoryFullConfiguration, FullClient};
+
+/// Seed of the key signing extrinsics on the development chain.
+const DEV_SEED: &str = "Alice";
+
+/// Merkle tree commands working on the node's database.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "merkle", about = "Merkle tree commands working on the database of a stopped node, or notarizing through a running one")]
+pub struct MerkleParams {
+	/// Specify the chain specification (one of dev or local, or a path to a chain spec file)
+	#[structopt(long = "chain", value_name = "CHAIN_SPEC")]
+	chain: Option<String>,
+
+	/// Specify the development chain
+	#[structopt(long = "dev")]
+	dev: bool,
+
+	/// Specify custom base path
+	#[structopt(long = "base-path", short = "d", value_name = "PATH", parse(from_os_str))]
+	base_path: Option<PathBuf>,
+
+	#[structopt(subcommand)]
+	cmd: MerkleCommand,
+}
+
+#[derive(Debug, StructOpt)]
+enum MerkleCommand {
+	/// Create a proof for a leaf, given by its hash or by the file that was inserted
+	#[structopt(name = "prove")]
+	Prove {
+		/// Hex hash of the leaf, or path of the file whose bytes were inserted
+		#[structopt(long = "leaf", value_name = "HEX|FILE")]
+		leaf: String,
+
+		/// Root of the tree state to prove against, the newest state by default
+		#[structopt(long = "root", value_name = "HEX")]
+		root: Option<String>,
+
+		/// Write the proof to a file instead of the standard output
+		#[structopt(long = "output", short = "o", value_name = "FILE", parse(from_os_str))]
+		output: Option<PathBuf>,
+	},
+
+	/// Verify a proof created by `merkle prove` against the chain
+	#[structopt(name = "verify")]
+	Verify {
+		/// Path of the proof
+		#[structopt(long = "proof", value_name = "FILE", parse(from_os_str))]
+		proof: PathBuf,
+	},
+
+	/// Export hashes of all leaves in order of insertion, as a JSON array
+	#[structopt(name = "export-leaves")]
+	ExportLeaves {
+		/// Write the leaves to a file instead of the standard output
+		#[structopt(long = "output", short = "o", value_name = "FILE", parse(from_os_str))]
+		output: Option<PathBuf>,
+	},
+
+	/// Print the root hash and the number of leaves at the best block
+	#[structopt(name = "root")]
+	Root,
+
+	/// Drop the merkle index and build it again from the chain
+	#[structopt(name = "rebuild-index")]
+	RebuildIndex,
+
+	/// Insert digests of files through a running node and write a receipt for each
+	#[structopt(name = "notarize")]
+	Notarize(NotarizeParams),
+}
+
+/// Run the `merkle` subcommand.
+pub fn run(params: MerkleParams, version: &VersionInfo) -> error::Result<()> {
+	if let MerkleCommand::Notarize(ref notarize_params) = params.cmd {
+		// Database is locked by the running node, only its keystore is read
+		let pair = match (&notarize_params.seed, params.dev) {
+			(Some(seed), _) => notarize::pair_from_seed(seed)?,
+			(None, true) => notarize::pair_from_seed(DEV_SEED)?,
+			(None, false) => {
+				let config = configuration(&params, version)?;
+				notarize::pair_from_keystore(&config.keystore_path, &notarize_params.password)?
+			},
+		};
+		return notarize::run(notarize_params, pair);
+	}
+
+	let config = configuration(&params, version)?;
+	let client = substrate_service::new_client::<Factory>(&config)?;
+	let index_dir = config.custom.merkle_index_dir.clone();
+
+	match params.cmd {
+		MerkleCommand::Root => {
+			let best_hash = client.best_hash().map_err(|e| e.to_string())?;
+			let root_hash = client.root_hash(best_hash).map_err(|e| e.to_string())?;
+			let n_nodes = client.n_nodes(best_hash).map_err(|e| e.to_string())?;
+			match root_hash {
+				Some(root_hash) => println!("{:?}", root_hash),
+				None => println!("Tree is empty"),
+			}
+			// Logger isn't set up for subcommands, details go to stderr so stdout stays scriptable
+			eprintln!("{} leaves at block {:?}", n_nodes, best_hash);
+		},
+		MerkleCommand::Prove { leaf, root, output } => {
+			let leaf = match parse_hash(&leaf) {
+				Some(leaf_hash) => leaf_hash,
+				// Not a hash, so it is the inserted file
+				None => ClientHasher::hash_leaf(&fs::read(&leaf)?),
+			};
+			let root = match root {
+				Some(root) => Some(parse_hash(&root).ok_or("Root is not a hex hash")?),
+				None => None,
+			};
+			let index = synced_index(&client, index_dir)?;
+			let proof = index.proof(LeafRef::Hash(leaf), root)?;
+			write_json(&proof, output)?;
+		},
+		MerkleCommand::Verify { proof } => {
+			let proof: MerkleProof = serde_json::from_reader(File::open(proof)?).map_err(|e| e.to_string())?;
+			let best_hash = client.best_hash().map_err(|e| e.to_string())?;
+			let n_nodes = client.n_nodes(best_hash).map_err(|e| e.to_string())?;
+			if !proof.verify(n_nodes) {
+				return Err("Proof is not valid".into());
+			}
+			// Proof can be valid for a made up root, so the root has to be one of the chain's tree states
+			let index = synced_index(&client, index_dir)?;
+			if index.tree().get_n_nodes_at(&proof.root).is_none() {
+				return Err(format!("Root {:?} is not a tree state of the best chain", proof.root).into());
+			}
+			println!("Proof is valid");
+		},
+		MerkleCommand::ExportLeaves { output } => {
+			let index = synced_index(&client, index_dir)?;
+			let tree = index.tree();
+			write_json(&tree.leaves, output)?;
+		},
+		MerkleCommand::RebuildIndex => {
+			if let Some(ref dir) = index_dir {
+				if fs::metadata(dir).is_ok() {
+					fs::remove_dir_all(dir)?;
+				}
+			}
+			let index = synced_index(&client, index_dir)?;
+			eprintln!("Merkle index rebuilt with {} leaves", index.tree().n_nodes);
+		},
+		MerkleCommand::Notarize(_) => unreachable!("notarize doesn't open the database; qed"),
+	}
+	Ok(())
+}
+
+// Configuration of the node whose database is opened
+fn configuration(params: &MerkleParams, version: &VersionInfo) -> error::Result<FactoryFullConfiguration<Factory>> {
+	let chain = match (params.dev, &params.chain) {
+		(true, _) => "dev".to_string(),
+		(false, Some(chain)) => chain.clone(),
+		(false, None) => return Err("Specify the chain with --chain or --dev".into()),
+	};
+	let spec = match chain_spec::Alternative::from(&chain) {
+		Some(spec) => spec.load()?,
+		None => chain_spec::ChainSpec::from_json_file(PathBuf::from(chain))?,
+	};
+
+	// Same folders as the ones used by the node
+	let base_path = match params.base_path {
+		Some(ref base_path) => base_path.clone(),
+		None => app_dirs::get_app_root(
+			AppDataType::UserData,
+			&AppInfo {
+				name: version.executable_name,
+				author: version.author,
+			},
+		).expect("app directories exist on all supported platforms; qed"),
+	};
+	let chain_path = base_path.join("chains").join(spec.id());
+	let db_path = chain_path.join("db");
+
+	let mut config = FactoryFullConfiguration::<Factory>::default_with_spec(spec);
+	config.database_path = db_path.to_string_lossy().into();
+	config.keystore_path = chain_path.join("keystore").to_string_lossy().into();
+	config.custom.merkle_index_dir = Some(db_path.join("merkle").to_string_lossy().into());
+	Ok(config)
+}
+
+// Index caught up with the best block, saved so the next command starts from there
+fn synced_index(client: &Arc<FullClient<Factory>>, index_dir: Option<String>) -> error::Result<MerkleIndex> {
+	let index = MerkleIndex::new(index_dir);
+	let best_hash = index.sync(client)?;
+	index.save_checkpoint(best_hash)?;
+	Ok(index)
+}
+
+// Hash given as `0x` prefixed hex
+fn parse_hash(hex: &str) -> Option<H256> {
+	serde_json::from_value(serde_json::Value::String(hex.to_string())).ok()
+}
+
+fn write_json<T: ::serde::Serialize>(value: &T, output: Option<PathBuf>) -> error::Result<()> {
+	let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+	match output {
+		Some(path) => File::create(path)?.write_all(json.as_bytes())?,
+		None => writeln!(io::stdout(), "{}", json)?,
+	}
+	Ok(())
+}
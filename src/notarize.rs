@@ -0,0 +1,291 @@
+//! `merkle notarize`, inserting digests of files through the RPC of a running node. Every file is
+//! inserted by its own signed `insert` extrinsic, and once the extrinsic is included, a receipt with
+//! the block and the proof of the leaf is written for the file. Files with the same contents share
+//! a single extrinsic and receipt.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use codec::{Decode, Encode};
+use futures::{Future, Stream};
+use hyper::{Body, Client, Request, Uri};
+use hyper::client::HttpConnector;
+use indexer::MerkleProof;
+use merkle_tree::ClientHasher;
+use primitives::{blake2_256, ed25519::Pair, hexdisplay::HexDisplay, storage::{StorageData, StorageKey}, twox_128, H256};
+use runtime_primitives::generic::Era;
+use runtime_primitives::traits::{BlakeTwo256, Hash};
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use substrate_cli::error;
+use substrate_keystore::Store;
+use substrate_merkle_tree_runtime::{opaque, AccountId, Call, Event, MerkleTreeCall, Nonce, UncheckedExtrinsic};
+use system::{self, EventRecord, Phase};
+use tokio::runtime::Runtime;
+
+/// Milliseconds between two polls of the node for new blocks and proofs.
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// Insert digests of files and write a receipt for each of them.
+#[derive(Debug, StructOpt)]
+pub struct NotarizeParams {
+	/// Files to notarize, directories are notarized file by file
+	#[structopt(value_name = "PATH", parse(from_os_str), raw(required = "true"))]
+	paths: Vec<PathBuf>,
+
+	/// Seed of the signing key, as hex or as a dev account name like Alice
+	#[structopt(long = "seed", value_name = "SEED")]
+	pub seed: Option<String>,
+
+	/// Password of the key in the node's keystore, used when there is no seed
+	#[structopt(long = "password", value_name = "PASSWORD", default_value = "")]
+	pub password: String,
+
+	/// Node RPC the extrinsics are submitted to and proofs are read from
+	#[structopt(long = "rpc-url", value_name = "URL", default_value = "http://127.0.0.1:9933")]
+	rpc_url: String,
+
+	/// Folder receipts are written to, named by the digest of the file
+	#[structopt(long = "receipts", value_name = "DIR", parse(from_os_str), default_value = "receipts")]
+	receipts: PathBuf,
+
+	/// Seconds to wait for all files to be included
+	#[structopt(long = "timeout", value_name = "SECS", default_value = "120")]
+	timeout: u64,
+}
+
+/// Proof that a file was notarized, also readable by `merkle verify` as a proof.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotarizeReceipt {
+	/// Path of the file when it was notarized.
+	pub path: PathBuf,
+	/// Blake2 256 hash of the file's bytes, the value that was inserted.
+	pub digest: H256,
+	/// Hash of the `insert` extrinsic.
+	pub extrinsic_hash: H256,
+	/// Hash of the block the extrinsic was included in.
+	pub block_hash: H256,
+	/// Number of the block the extrinsic was included in.
+	pub block_number: u64,
+	/// Proof of the leaf against the root at the time the receipt was written.
+	#[serde(flatten)]
+	pub proof: MerkleProof,
+}
+
+// File whose extrinsic was submitted, waiting to be included
+struct Submitted {
+	path: PathBuf,
+	digest: H256,
+}
+
+// JSON-RPC over HTTP, blocking on every call
+struct RpcClient {
+	runtime: Runtime,
+	client: Client<HttpConnector>,
+	next_id: u64,
+}
+
+impl RpcClient {
+	fn new() -> io::Result<RpcClient> {
+		Ok(RpcClient {
+			runtime: Runtime::new()?,
+			client: Client::new(),
+			next_id: 0,
+		})
+	}
+
+	fn call<T: DeserializeOwned>(&mut self, url: &Uri, method: &str, params: Value) -> Result<T, String> {
+		self.next_id += 1;
+		let request = json!({ "jsonrpc": "2.0", "id": self.next_id, "method": method, "params": params });
+		let request = Request::post(url.clone())
+			.header("Content-Type", "application/json")
+			.body(Body::from(request.to_string()))
+			.map_err(|e| e.to_string())?;
+		let body = self.runtime.block_on(self.client.request(request).and_then(|response| response.into_body().concat2()))
+			.map_err(|e| format!("Request to {} failed: {}", url, e))?;
+		let mut response: Value = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+		if let Some(error) = response.get("error") {
+			return Err(format!("{} failed: {}", method, error));
+		}
+		serde_json::from_value(response["result"].take()).map_err(|e| format!("Invalid {} response: {}", method, e))
+	}
+}
+
+/// Key of a dev account like Alice, or of a hex seed.
+pub fn pair_from_seed(seed: &str) -> Result<Pair, String> {
+	if seed.starts_with("0x") {
+		let seed: H256 = serde_json::from_value(Value::String(seed.to_string())).map_err(|_| "Seed is not 32 hex bytes")?;
+		return Ok(Pair::from_seed(&seed.0));
+	}
+	// Dev account seeds are their names padded with spaces
+	if seed.len() > 32 {
+		return Err("Seed name is longer than 32 bytes".into());
+	}
+	let mut bytes = [b' '; 32];
+	bytes[..seed.len()].copy_from_slice(seed.as_bytes());
+	Ok(Pair::from_seed(&bytes))
+}
+
+/// First key of the keystore at `path`.
+pub fn pair_from_keystore(path: &str, password: &str) -> Result<Pair, String> {
+	let store = Store::open(PathBuf::from(path)).map_err(|e| e.to_string())?;
+	let public = store.contents().map_err(|e| e.to_string())?.into_iter().next()
+		.ok_or_else(|| format!("Keystore {} is empty, specify the key with --seed", path))?;
+	store.load(&public, password).map_err(|e| e.to_string())
+}
+
+/// Notarize the files of `params`, signing with `pair`.
+pub fn run(params: &NotarizeParams, pair: Pair) -> error::Result<()> {
+	let mut files = vec![];
+	for path in &params.paths {
+		collect_files(path, &mut files)?;
+	}
+	if files.is_empty() {
+		return Err("No files to notarize".into());
+	}
+	let node_url = parse_url(&params.rpc_url)?;
+	let mut rpc = RpcClient::new()?;
+
+	let account: AccountId = pair.public().0.into();
+	let genesis_hash: H256 = rpc.call::<Option<H256>>(&node_url, "chain_getBlockHash", json!([0]))?
+		.ok_or("Node has no genesis block")?;
+	let mut nonce = account_nonce(&mut rpc, &node_url, &account)?;
+	// Extrinsics are submitted after this block, so it's the first one not searched for them
+	let best: opaque::Header = rpc.call(&node_url, "chain_getHeader", json!([]))?;
+
+	let mut submitted = HashMap::new();
+	let mut digests = HashMap::new();
+	for path in files {
+		let digest = H256::from(blake2_256(&fs::read(&path)?));
+		// Receipts are named by digest, so a digest is inserted only once
+		if let Some(first) = digests.get(&digest) {
+			eprintln!("Skipping {}, it has the same contents as {}", path.display(), first.display());
+			continue;
+		}
+		digests.insert(digest, path.clone());
+		let extrinsic = sign_insert(&pair, account, nonce, genesis_hash, digest);
+		let encoded = format!("0x{}", HexDisplay::from(&extrinsic.encode()));
+		let extrinsic_hash: H256 = rpc.call(&node_url, "author_submitExtrinsic", json!([encoded]))?;
+		eprintln!("Submitted {} as extrinsic {:?}", path.display(), extrinsic_hash);
+		submitted.insert(extrinsic_hash, Submitted { path, digest });
+		nonce += 1;
+	}
+
+	fs::create_dir_all(&params.receipts)?;
+	let deadline = Instant::now() + Duration::from_secs(params.timeout);
+	let mut block_number = best.number + 1;
+	while !submitted.is_empty() {
+		if Instant::now() > deadline {
+			return Err(format!("{} files were not included within {} seconds", submitted.len(), params.timeout).into());
+		}
+		let block_hash = match rpc.call::<Option<H256>>(&node_url, "chain_getBlockHash", json!([block_number]))? {
+			Some(block_hash) => block_hash,
+			None => {
+				thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+				continue;
+			},
+		};
+		let mut block: Value = rpc.call(&node_url, "chain_getBlock", json!([block_hash]))?;
+		let extrinsics: Vec<opaque::UncheckedExtrinsic> = serde_json::from_value(block["block"]["extrinsics"].take())
+			.map_err(|e| format!("Invalid block {:?}: {}", block_hash, e))?;
+		for (extrinsic_index, extrinsic) in extrinsics.iter().enumerate() {
+			// Same hash as returned when submitting, the hash of the encoded extrinsic
+			let extrinsic_hash = BlakeTwo256::hash_of(extrinsic);
+			let file = match submitted.remove(&extrinsic_hash) {
+				Some(file) => file,
+				None => continue,
+			};
+			// Leaf of a failed extrinsic is never indexed, so there is no proof to wait for
+			if extrinsic_failed(&mut rpc, &node_url, block_hash, extrinsic_index as u32)? {
+				return Err(format!("Inserting {} failed in block #{} ({:?})", file.path.display(), block_number, block_hash).into());
+			}
+			let proof = wait_for_proof(&mut rpc, &node_url, file.digest, deadline)?;
+			println!("{}: leaf #{} in block #{} ({:?})", file.path.display(), proof.leaf_index, block_number, block_hash);
+			let receipt = NotarizeReceipt {
+				path: file.path,
+				digest: file.digest,
+				extrinsic_hash,
+				block_hash,
+				block_number,
+				proof,
+			};
+			let json = serde_json::to_string_pretty(&receipt).map_err(|e| e.to_string())?;
+			File::create(params.receipts.join(format!("{:x}.json", receipt.digest)))?.write_all(json.as_bytes())?;
+		}
+		block_number += 1;
+	}
+	Ok(())
+}
+
+fn parse_url(url: &str) -> Result<Uri, String> {
+	url.parse().map_err(|e| format!("Invalid URL {}: {}", url, e))
+}
+
+// Files inside `path` in a stable order, `path` itself if it's a file
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+	if !fs::metadata(path)?.is_dir() {
+		files.push(path.to_path_buf());
+		return Ok(());
+	}
+	let mut entries = fs::read_dir(path)?
+		.map(|entry| entry.map(|entry| entry.path()))
+		.collect::<io::Result<Vec<_>>>()?;
+	entries.sort();
+	for entry in entries {
+		collect_files(&entry, files)?;
+	}
+	Ok(())
+}
+
+// Nonce of the next extrinsic signed by `account`
+fn account_nonce(rpc: &mut RpcClient, url: &Uri, account: &AccountId) -> Result<Nonce, String> {
+	let mut key = b"System AccountNonce".to_vec();
+	account.encode_to(&mut key);
+	let key = StorageKey(twox_128(&key).to_vec());
+	match rpc.call::<Option<StorageData>>(url, "state_getStorage", json!([key]))? {
+		Some(data) => Nonce::decode(&mut &data.0[..]).ok_or_else(|| "Could not decode account nonce".into()),
+		None => Ok(0),
+	}
+}
+
+// Immortal `insert` extrinsic of `digest`
+fn sign_insert(pair: &Pair, account: AccountId, nonce: Nonce, genesis_hash: H256, digest: H256) -> UncheckedExtrinsic {
+	let call = Call::MerkleTree(MerkleTreeCall::insert(digest.to_vec()));
+	// Immortal extrinsics are signed together with the genesis hash
+	let payload = (nonce, call, Era::Immortal, genesis_hash);
+	let signature = payload.using_encoded(|payload| pair.sign(payload));
+	let (nonce, call, era, _) = payload;
+	UncheckedExtrinsic::new_signed(nonce, call, account.into(), signature.into(), era)
+}
+
+// Whether the extrinsic at `extrinsic_index` of block `at` failed, read from the block's system events
+fn extrinsic_failed(rpc: &mut RpcClient, url: &Uri, at: H256, extrinsic_index: u32) -> Result<bool, String> {
+	let key = StorageKey(twox_128(b"System Events").to_vec());
+	let events = match rpc.call::<Option<StorageData>>(url, "state_getStorage", json!([key, at]))? {
+		Some(data) => Vec::<EventRecord<Event>>::decode(&mut &data.0[..]).ok_or("Could not decode block events")?,
+		None => vec![],
+	};
+	Ok(events.into_iter().any(|record| match (record.phase, record.event) {
+		(Phase::ApplyExtrinsic(index), Event::system(system::Event::ExtrinsicFailed)) => index == extrinsic_index,
+		_ => false,
+	}))
+}
+
+// Proof of the leaf made from `digest`, once the merkle index of the node has indexed it
+fn wait_for_proof(rpc: &mut RpcClient, url: &Uri, digest: H256, deadline: Instant) -> Result<MerkleProof, String> {
+	let leaf_hash = ClientHasher::hash_leaf(digest.as_ref());
+	loop {
+		match rpc.call(url, "merkle_getProof", json!([leaf_hash])) {
+			Ok(proof) => return Ok(proof),
+			Err(e) => if Instant::now() > deadline {
+				return Err(format!("Leaf {:?} was not indexed: {}", leaf_hash, e));
+			},
+		}
+		thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+	}
+}
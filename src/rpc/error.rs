@@ -0,0 +1,34 @@
+//! Merkle RPC errors.
+
+use client;
+use jsonrpc_core as rpc;
+
+error_chain! {
+	links {
+		Client(client::error::Error, client::error::ErrorKind) #[doc = "Client error"];
+	}
+	errors {
+		/// Leaf or tree state is not known to the node.
+		NotFound(msg: String) {
+			description("not found"),
+			display("Not found: {}", msg),
+		}
+	}
+}
+
+const NOT_FOUND_ERROR: i64 = 1;
+const CLIENT_ERROR: i64 = 2;
+
+impl From<Error> for rpc::Error {
+	fn from(e: Error) -> Self {
+		let code = match e {
+			Error(ErrorKind::NotFound(_), _) => NOT_FOUND_ERROR,
+			_ => CLIENT_ERROR,
+		};
+		rpc::Error {
+			code: rpc::ErrorCode::ServerError(code),
+			message: e.to_string(),
+			data: None,
+		}
+	}
+}
@@ -0,0 +1,337 @@
+//! JSON-RPC of the merkle tree, served next to the node's own RPC. Root, leaf count and stored leaves are read
+//! from the runtime, proofs, leaves and subscriptions are served by the node's merkle index. Subscriptions are
+//! only available over WebSocket.
+
+use std::cmp;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::{Future, Sink, Stream};
+use indexer::{MerkleIndex, MerkleInsert};
+pub use indexer::{InclusionBlock, InclusionReceipt, LeafRef, MerkleProof};
+use jsonrpc_core::Result as RpcResult;
+use jsonrpc_macros::{pubsub, Trailing};
+use jsonrpc_pubsub::SubscriptionId;
+use primitives::H256;
+use runtime_primitives::generic::BlockId;
+use runtime_primitives::traits::ProvideRuntimeApi;
+use service::Factory;
+use substrate_merkle_tree_runtime::merkle_tree_api::MerkleTreeApi;
+use substrate_merkle_tree_runtime::opaque::Block;
+use substrate_rpc_servers::{self as servers, apis};
+use substrate_service::{FactoryFullConfiguration, FullClient, FullComponents, Properties, TaskExecutor};
+
+pub mod error;
+mod subscriptions;
+
+#[cfg(test)]
+mod tests;
+
+use self::error::{ErrorKind, Result};
+pub use self::apis::metadata::Metadata;
+use self::subscriptions::Subscriptions;
+
+/// Maximum number of leaves returned by a single `merkle_getLeaves` call.
+const MAX_LEAVES: u64 = 1000;
+
+build_rpc_trait! {
+	/// Merkle tree RPC methods.
+	pub trait MerkleApi {
+		type Metadata;
+
+		/// Root hash of the on-chain tree at block `at`, or at the best block.
+		#[rpc(name = "merkle_root")]
+		fn root(&self, Trailing<H256>) -> Result<Option<H256>>;
+
+		/// Number of leaves in the on-chain tree at block `at`, or at the best block.
+		#[rpc(name = "merkle_leafCount")]
+		fn leaf_count(&self, Trailing<H256>) -> Result<u64>;
+
+		/// Proof for the leaf inside the tree state with `root`, or inside the newest state.
+		/// Leaf inserted more than once is found by hash at its last index.
+		#[rpc(name = "merkle_getProof")]
+		fn get_proof(&self, LeafRef, Trailing<H256>) -> Result<MerkleProof>;
+
+		/// Check a proof the same way the runtime does, bounded by the leaf count of the best block.
+		#[rpc(name = "merkle_verifyProof")]
+		fn verify_proof(&self, MerkleProof) -> Result<bool>;
+
+		/// Proof like `merkle_getProof`, together with the block, extrinsic and account that inserted the leaf.
+		#[rpc(name = "merkle_getReceipt")]
+		fn get_receipt(&self, LeafRef, Trailing<H256>) -> Result<InclusionReceipt>;
+
+		/// Check that the receipt's root is a tree state of the best chain, that its proof is valid
+		/// and that the leaf was inserted by the receipt's block.
+		#[rpc(name = "merkle_verifyReceipt")]
+		fn verify_receipt(&self, InclusionReceipt) -> Result<bool>;
+
+		/// Hashes of at most `count` leaves starting from index `start`.
+		#[rpc(name = "merkle_getLeaves")]
+		fn get_leaves(&self, u64, u64) -> Result<Vec<H256>>;
+
+		/// Hash of the leaf at `index` stored on-chain at block `at`, or at the best block.
+		#[rpc(name = "merkle_leafAt")]
+		fn leaf_at(&self, u64, Trailing<H256>) -> Result<Option<H256>>;
+
+		/// Indices `leaf` was inserted at, stored on-chain at block `at`, or at the best block.
+		#[rpc(name = "merkle_indicesOf")]
+		fn indices_of(&self, H256, Trailing<H256>) -> Result<Vec<u64>>;
+
+		/// Proof for the leaf at `index` against the root of block `at`, or of the best block,
+		/// created by the runtime from the stored leaves instead of the merkle index.
+		#[rpc(name = "merkle_getChainProof")]
+		fn get_chain_proof(&self, u64, Trailing<H256>) -> Result<MerkleProof>;
+
+		#[pubsub(name = "merkle_roots")] {
+			/// New root of every block that inserted leaves.
+			#[rpc(name = "merkle_subscribeRoots")]
+			fn subscribe_roots(&self, Self::Metadata, pubsub::Subscriber<MerkleInsert>);
+
+			/// Unsubscribe from new roots.
+			#[rpc(name = "merkle_unsubscribeRoots")]
+			fn unsubscribe_roots(&self, SubscriptionId) -> RpcResult<bool>;
+		}
+
+		#[pubsub(name = "merkle_leaves")] {
+			/// Every inserted leaf.
+			#[rpc(name = "merkle_subscribeLeaves")]
+			fn subscribe_leaves(&self, Self::Metadata, pubsub::Subscriber<MerkleInsert>);
+
+			/// Unsubscribe from inserted leaves.
+			#[rpc(name = "merkle_unsubscribeLeaves")]
+			fn unsubscribe_leaves(&self, SubscriptionId) -> RpcResult<bool>;
+		}
+	}
+}
+
+/// On-chain tree state the RPC reads from.
+pub trait MerkleChain: Send + Sync + 'static {
+	/// Hash of the best block.
+	fn best_hash(&self) -> Result<H256>;
+	/// Root hash of the tree at block `at`.
+	fn root_hash(&self, at: H256) -> Result<Option<H256>>;
+	/// Number of leaves in the tree at block `at`.
+	fn n_nodes(&self, at: H256) -> Result<u128>;
+	/// Hash of the leaf at `index` stored at block `at`.
+	fn leaf_at(&self, at: H256, index: u128) -> Result<Option<H256>>;
+	/// Indices `leaf` was inserted at, stored at block `at`.
+	fn indices_of(&self, at: H256, leaf: H256) -> Result<Vec<u128>>;
+	/// Proof for the leaf at `index` against the root of block `at`, made from the stored leaves.
+	fn proof_at(&self, at: H256, index: u128) -> Result<Option<Vec<Option<H256>>>>;
+}
+
+impl MerkleChain for FullClient<Factory> {
+	fn best_hash(&self) -> Result<H256> {
+		Ok(self.info()?.chain.best_hash)
+	}
+
+	fn root_hash(&self, at: H256) -> Result<Option<H256>> {
+		Ok(self.runtime_api().root_hash(&BlockId::Hash(at))?)
+	}
+
+	fn n_nodes(&self, at: H256) -> Result<u128> {
+		Ok(self.runtime_api().n_nodes(&BlockId::Hash(at))?)
+	}
+
+	fn leaf_at(&self, at: H256, index: u128) -> Result<Option<H256>> {
+		Ok(self.runtime_api().leaf_at(&BlockId::Hash(at), index)?)
+	}
+
+	fn indices_of(&self, at: H256, leaf: H256) -> Result<Vec<u128>> {
+		Ok(self.runtime_api().indices_of(&BlockId::Hash(at), leaf)?)
+	}
+
+	fn proof_at(&self, at: H256, index: u128) -> Result<Option<Vec<Option<H256>>>> {
+		Ok(self.runtime_api().proof_at(&BlockId::Hash(at), index)?)
+	}
+}
+
+/// Merkle RPC handler.
+pub struct Merkle<C> {
+	chain: Arc<C>,
+	index: Arc<MerkleIndex>,
+	subscriptions: Subscriptions,
+}
+
+impl<C: MerkleChain> Merkle<C> {
+	/// Create a handler reading from `chain` and `index`, running subscriptions on `executor`.
+	pub fn new(chain: Arc<C>, index: Arc<MerkleIndex>, executor: TaskExecutor) -> Self {
+		Merkle {
+			chain,
+			index,
+			subscriptions: Subscriptions::new(executor),
+		}
+	}
+
+	// Forward inserts from `stream` to `subscriber`
+	fn subscribe<S>(&self, subscriber: pubsub::Subscriber<MerkleInsert>, stream: S) where
+		S: Stream<Item = MerkleInsert, Error = ()> + Send + 'static,
+	{
+		self.subscriptions.add(subscriber, |sink| {
+			sink.sink_map_err(|e| warn!(target: "merkle", "Error sending notifications: {:?}", e))
+				.send_all(stream.map(Ok))
+				.map(|_| ())
+		})
+	}
+
+	fn at(&self, at: Trailing<H256>) -> Result<H256> {
+		match at.into() {
+			Some(at) => Ok(at),
+			None => self.chain.best_hash(),
+		}
+	}
+}
+
+impl<C: MerkleChain> MerkleApi for Merkle<C> {
+	type Metadata = Metadata;
+
+	fn root(&self, at: Trailing<H256>) -> Result<Option<H256>> {
+		let at = self.at(at)?;
+		self.chain.root_hash(at)
+	}
+
+	fn leaf_count(&self, at: Trailing<H256>) -> Result<u64> {
+		let at = self.at(at)?;
+		Ok(self.chain.n_nodes(at)? as u64)
+	}
+
+	fn get_proof(&self, leaf: LeafRef, root: Trailing<H256>) -> Result<MerkleProof> {
+		self.index.proof(leaf, root.into()).map_err(|e| ErrorKind::NotFound(e).into())
+	}
+
+	fn verify_proof(&self, proof: MerkleProof) -> Result<bool> {
+		let n_nodes = self.chain.n_nodes(self.chain.best_hash()?)?;
+		Ok(proof.verify(n_nodes))
+	}
+
+	fn get_receipt(&self, leaf: LeafRef, root: Trailing<H256>) -> Result<InclusionReceipt> {
+		self.index.receipt(leaf, root.into()).map_err(|e| ErrorKind::NotFound(e).into())
+	}
+
+	fn verify_receipt(&self, receipt: InclusionReceipt) -> Result<bool> {
+		Ok(self.index.verify_receipt(&receipt).is_ok())
+	}
+
+	fn get_leaves(&self, start: u64, count: u64) -> Result<Vec<H256>> {
+		let count = cmp::min(count, MAX_LEAVES);
+		let tree = self.index.tree();
+		Ok(tree.leaves.iter().skip(start as usize).take(count as usize).cloned().collect())
+	}
+
+	fn leaf_at(&self, index: u64, at: Trailing<H256>) -> Result<Option<H256>> {
+		let at = self.at(at)?;
+		self.chain.leaf_at(at, index as u128)
+	}
+
+	fn indices_of(&self, leaf: H256, at: Trailing<H256>) -> Result<Vec<u64>> {
+		let at = self.at(at)?;
+		Ok(self.chain.indices_of(at, leaf)?.into_iter().map(|index| index as u64).collect())
+	}
+
+	fn get_chain_proof(&self, index: u64, at: Trailing<H256>) -> Result<MerkleProof> {
+		let at = self.at(at)?;
+		let not_found = || ErrorKind::NotFound(format!("Leaf {} is not stored at block {}", index, at));
+		let leaf_hash = self.chain.leaf_at(at, index as u128)?.ok_or_else(not_found)?;
+		let root = self.chain.root_hash(at)?.ok_or_else(not_found)?;
+		let proof = self.chain.proof_at(at, index as u128)?.ok_or_else(not_found)?;
+		Ok(MerkleProof {
+			leaf_index: index,
+			leaf_hash,
+			root,
+			proof,
+		})
+	}
+
+	fn subscribe_roots(&self, _meta: Self::Metadata, subscriber: pubsub::Subscriber<MerkleInsert>) {
+		self.subscribe(subscriber, self.index.root_notification_stream())
+	}
+
+	fn unsubscribe_roots(&self, id: SubscriptionId) -> RpcResult<bool> {
+		Ok(self.subscriptions.cancel(id))
+	}
+
+	fn subscribe_leaves(&self, _meta: Self::Metadata, subscriber: pubsub::Subscriber<MerkleInsert>) {
+		self.subscribe(subscriber, self.index.leaf_notification_stream())
+	}
+
+	fn unsubscribe_leaves(&self, id: SubscriptionId) -> RpcResult<bool> {
+		Ok(self.subscriptions.cancel(id))
+	}
+}
+
+/// Node information returned by the `system_*` methods.
+#[derive(Clone)]
+pub struct SystemInfo {
+	chain_name: String,
+	properties: Properties,
+	impl_name: &'static str,
+	impl_version: &'static str,
+}
+
+impl SystemInfo {
+	/// Information of the node started with `config`.
+	pub fn new(config: &FactoryFullConfiguration<Factory>) -> Self {
+		SystemInfo {
+			chain_name: config.chain_spec.name().to_string(),
+			properties: config.chain_spec.properties(),
+			impl_name: config.impl_name,
+			impl_version: config.impl_version,
+		}
+	}
+}
+
+impl apis::system::SystemApi for SystemInfo {
+	fn system_name(&self) -> apis::system::error::Result<String> {
+		Ok(self.impl_name.into())
+	}
+
+	fn system_version(&self) -> apis::system::error::Result<String> {
+		Ok(self.impl_version.into())
+	}
+
+	fn system_chain(&self) -> apis::system::error::Result<String> {
+		Ok(self.chain_name.clone())
+	}
+
+	fn system_properties(&self) -> apis::system::error::Result<Properties> {
+		Ok(self.properties.clone())
+	}
+}
+
+/// Serve the node's RPC together with the merkle RPC over HTTP on `http_addr` and over WebSocket on `ws_addr`
+/// until `on_exit` resolves. Replaces the servers of the service, whose handler can't be extended.
+pub fn start<E>(
+	http_addr: Option<SocketAddr>,
+	ws_addr: Option<SocketAddr>,
+	system: SystemInfo,
+	service: &FullComponents<Factory>,
+	index: Arc<MerkleIndex>,
+	executor: TaskExecutor,
+	on_exit: E,
+) -> io::Result<()> where
+	E: Future<Item = (), Error = ()> + Clone + Send + 'static,
+{
+	let handler = || {
+		let client = service.client();
+		let subscriptions = apis::Subscriptions::new(executor.clone());
+		let chain = apis::chain::Chain::new(client.clone(), subscriptions.clone());
+		let state = apis::state::State::new(client.clone(), subscriptions.clone());
+		let author = apis::author::Author::new(client.clone(), service.transaction_pool(), subscriptions);
+		let mut io = servers::rpc_handler::<Block, H256, _, _, _, _>(state, chain, author, system.clone());
+		io.extend_with(Merkle::new(client, index.clone(), executor.clone()).to_delegate());
+		io
+	};
+
+	if let Some(addr) = http_addr {
+		let server = servers::start_http(&addr, handler())?;
+		info!("RPC listening on http://{}", addr);
+		executor.spawn(on_exit.clone().map(move |_| server.close()));
+	}
+	if let Some(addr) = ws_addr {
+		let server = servers::start_ws(&addr, handler())?;
+		info!("RPC listening on ws://{}", addr);
+		executor.spawn(on_exit.map(move |_| server.close()));
+	}
+	Ok(())
+}
@@ -0,0 +1,64 @@
+//! Active RPC subscriptions, each forwarding a stream of notifications to its subscriber.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Future, IntoFuture, sync::oneshot};
+use jsonrpc_pubsub::SubscriptionId;
+use jsonrpc_pubsub::typed::{Sink, Subscriber};
+use parking_lot::Mutex;
+use substrate_service::TaskExecutor;
+
+/// Subscriptions of all connections, cancelled by id.
+pub struct Subscriptions {
+	next_id: AtomicUsize,
+	active_subscriptions: Arc<Mutex<HashMap<SubscriptionId, oneshot::Sender<()>>>>,
+	executor: TaskExecutor,
+}
+
+impl Subscriptions {
+	/// Create subscriptions whose notifications are forwarded by tasks on `executor`.
+	pub fn new(executor: TaskExecutor) -> Self {
+		Subscriptions {
+			next_id: AtomicUsize::new(0),
+			active_subscriptions: Arc::new(Mutex::new(HashMap::new())),
+			executor,
+		}
+	}
+
+	/// Assign an id to `subscriber` and run the future made by `into_future` until it is done or
+	/// the subscription is cancelled. Subscription is removed once the future is done.
+	pub fn add<T, E, G, R, F>(&self, subscriber: Subscriber<T, E>, into_future: G) where
+		G: FnOnce(Sink<T, E>) -> R,
+		R: IntoFuture<Future = F, Item = (), Error = ()>,
+		F: Future<Item = (), Error = ()> + Send + 'static,
+	{
+		let id = SubscriptionId::Number(self.next_id.fetch_add(1, Ordering::AcqRel) as u64);
+		if let Ok(sink) = subscriber.assign_id(id.clone()) {
+			let (cancel, cancelled) = oneshot::channel();
+			let active_subscriptions = self.active_subscriptions.clone();
+			let finished_id = id.clone();
+			let future = into_future(sink)
+				.into_future()
+				.select(cancelled.map_err(|_| ()))
+				.then(move |_| {
+					active_subscriptions.lock().remove(&finished_id);
+					Ok(())
+				});
+			self.active_subscriptions.lock().insert(id, cancel);
+			self.executor.spawn(future);
+		}
+	}
+
+	/// Cancel subscription `id`, returns `false` if it doesn't exist.
+	pub fn cancel(&self, id: SubscriptionId) -> bool {
+		match self.active_subscriptions.lock().remove(&id) {
+			Some(cancel) => {
+				let _ = cancel.send(());
+				true
+			},
+			None => false,
+		}
+	}
+}
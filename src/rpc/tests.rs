@@ -0,0 +1,236 @@
+use super::*;
+
+use futures::sync::mpsc;
+use jsonrpc_pubsub::typed::Subscriber;
+use merkle_tree::{ClientHasher, MerkleTreeClient};
+use serde_json::Value;
+use tokio::runtime::Runtime;
+
+// Chain whose best block has the same tree as the index
+struct TestChain {
+	root_hash: Option<H256>,
+	n_nodes: u128,
+	leaves: Vec<H256>,
+}
+
+impl MerkleChain for TestChain {
+	fn best_hash(&self) -> Result<H256> {
+		Ok(block(2))
+	}
+
+	fn root_hash(&self, _at: H256) -> Result<Option<H256>> {
+		Ok(self.root_hash)
+	}
+
+	fn n_nodes(&self, _at: H256) -> Result<u128> {
+		Ok(self.n_nodes)
+	}
+
+	fn leaf_at(&self, _at: H256, index: u128) -> Result<Option<H256>> {
+		Ok(self.leaves.get(index as usize).cloned())
+	}
+
+	fn indices_of(&self, _at: H256, leaf: H256) -> Result<Vec<u128>> {
+		Ok(self.leaves.iter().enumerate().filter(|(_, l)| **l == leaf).map(|(i, _)| i as u128).collect())
+	}
+
+	fn proof_at(&self, _at: H256, index: u128) -> Result<Option<Vec<Option<H256>>>> {
+		let tree = MerkleTreeClient::from_leaves(self.leaves.clone(), None).unwrap();
+		Ok(self.root_hash.and_then(|root| tree.get_proof_for_index(index, root).ok()))
+	}
+}
+
+fn block(number: u8) -> H256 {
+	H256::from([number; 32])
+}
+
+fn leaf(value: &str) -> H256 {
+	ClientHasher::hash_leaf(value.as_bytes())
+}
+
+// Index with `a`, `b` and `c` inserted in block 1, `d` and `e` in block 2
+// Runtime runs the subscriptions and has to be kept alive by the test
+fn setup() -> (Merkle<TestChain>, H256, Runtime) {
+	let runtime = Runtime::new().unwrap();
+	let index = Arc::new(MerkleIndex::new(None));
+	index.import_leaves(block(1), block(0), 1, vec![leaf("a"), leaf("b"), leaf("c")]).unwrap();
+	let old_root = index.tree().root_hash.unwrap();
+	index.import_leaves(block(2), block(1), 2, vec![leaf("d"), leaf("e")]).unwrap();
+
+	let chain = TestChain {
+		root_hash: index.tree().root_hash,
+		n_nodes: index.tree().n_nodes,
+		leaves: index.tree().leaves.clone(),
+	};
+	(Merkle::new(Arc::new(chain), index, runtime.executor()), old_root, runtime)
+}
+
+// Next `n` inserts sent to the subscriber reading from `transport`
+fn notifications(transport: &mut mpsc::Receiver<String>, n: u64) -> Vec<MerkleInsert> {
+	transport.by_ref().take(n).collect().wait().unwrap().iter().map(|notification| {
+		let mut notification: Value = ::serde_json::from_str(notification).unwrap();
+		::serde_json::from_value(notification["params"]["result"].take()).unwrap()
+	}).collect()
+}
+
+// Inserts of `leaves` by block 3, on top of the index of `setup`
+fn block_3_inserts(merkle: &Merkle<TestChain>, leaves: &[&str], finalized: bool) -> Vec<MerkleInsert> {
+	leaves.iter().enumerate().map(|(i, value)| MerkleInsert {
+		block_hash: block(3),
+		block_number: 3,
+		leaf_index: 5 + i as u64,
+		leaf_hash: leaf(value),
+		new_root: merkle.index.tree().get_root_at(6 + i as u128).unwrap(),
+		finalized,
+	}).collect()
+}
+
+#[test]
+fn should_return_root_and_leaf_count_of_the_chain() {
+	let (merkle, _, _runtime) = setup();
+
+	assert_eq!(merkle.root(None.into()).unwrap(), merkle.index.tree().root_hash);
+	assert_eq!(merkle.leaf_count(None.into()).unwrap(), 5);
+}
+
+#[test]
+fn should_create_proofs_that_verify() {
+	let (merkle, old_root, _runtime) = setup();
+
+	let by_index = merkle.get_proof(LeafRef::Index(2), None.into()).unwrap();
+	let by_hash = merkle.get_proof(LeafRef::Hash(leaf("c")), None.into()).unwrap();
+	assert_eq!(by_index, by_hash);
+	assert_eq!(by_index.leaf_hash, leaf("c"));
+	assert_eq!(merkle.verify_proof(by_index).unwrap(), true);
+
+	// Proof for a previous tree state
+	let old_proof = merkle.get_proof(LeafRef::Index(2), Some(old_root).into()).unwrap();
+	assert_eq!(old_proof.root, old_root);
+	assert_eq!(merkle.verify_proof(old_proof).unwrap(), true);
+
+	let mut wrong_leaf = merkle.get_proof(LeafRef::Index(1), None.into()).unwrap();
+	wrong_leaf.leaf_hash = leaf("x");
+	assert_eq!(merkle.verify_proof(wrong_leaf).unwrap(), false);
+}
+
+#[test]
+fn should_not_find_leaves_outside_of_the_tree_state() {
+	let (merkle, old_root, _runtime) = setup();
+
+	assert!(merkle.get_proof(LeafRef::Index(5), None.into()).is_err());
+	assert!(merkle.get_proof(LeafRef::Hash(leaf("x")), None.into()).is_err());
+	assert!(merkle.get_proof(LeafRef::Hash(leaf("d")), Some(old_root).into()).is_err());
+}
+
+#[test]
+fn should_create_receipts_with_the_inserting_block() {
+	let (merkle, old_root, _runtime) = setup();
+
+	let receipt = merkle.get_receipt(LeafRef::Hash(leaf("d")), None.into()).unwrap();
+	assert_eq!(receipt.index, 3);
+	assert_eq!(receipt.block.hash, block(2));
+	assert_eq!(receipt.block.number, 2);
+	assert_eq!(receipt.block.extrinsic_index, Some(0));
+	assert_eq!(receipt.timestamp, 2);
+	assert_eq!(merkle.verify_receipt(receipt.clone()).unwrap(), true);
+
+	// Receipt for a previous tree state
+	let old_receipt = merkle.get_receipt(LeafRef::Index(2), Some(old_root).into()).unwrap();
+	assert_eq!(old_receipt.block.hash, block(1));
+	assert_eq!(old_receipt.block.extrinsic_index, Some(2));
+	assert_eq!(merkle.verify_receipt(old_receipt).unwrap(), true);
+
+	let mut wrong_block = receipt.clone();
+	wrong_block.block.hash = block(1);
+	assert_eq!(merkle.verify_receipt(wrong_block).unwrap(), false);
+
+	let mut wrong_timestamp = receipt.clone();
+	wrong_timestamp.timestamp = 1;
+	assert_eq!(merkle.verify_receipt(wrong_timestamp).unwrap(), false);
+
+	let mut wrong_leaf = receipt;
+	wrong_leaf.leaf = leaf("x");
+	assert_eq!(merkle.verify_receipt(wrong_leaf).unwrap(), false);
+}
+
+#[test]
+fn should_return_pages_of_leaves() {
+	let (merkle, _, _runtime) = setup();
+
+	assert_eq!(merkle.get_leaves(1, 2).unwrap(), vec![leaf("b"), leaf("c")]);
+	assert_eq!(merkle.get_leaves(4, 10).unwrap(), vec![leaf("e")]);
+	assert_eq!(merkle.get_leaves(5, 10).unwrap(), vec![]);
+}
+
+#[test]
+fn should_answer_membership_and_create_proofs_from_the_chain() {
+	let (merkle, _, _runtime) = setup();
+
+	assert_eq!(merkle.leaf_at(3, None.into()).unwrap(), Some(leaf("d")));
+	assert_eq!(merkle.leaf_at(5, None.into()).unwrap(), None);
+	assert_eq!(merkle.indices_of(leaf("b"), None.into()).unwrap(), vec![1]);
+	assert_eq!(merkle.indices_of(leaf("x"), None.into()).unwrap(), Vec::<u64>::new());
+
+	// Same proof as the one of the index
+	let chain_proof = merkle.get_chain_proof(3, None.into()).unwrap();
+	assert_eq!(chain_proof, merkle.get_proof(LeafRef::Index(3), None.into()).unwrap());
+	assert_eq!(merkle.verify_proof(chain_proof).unwrap(), true);
+	assert!(merkle.get_chain_proof(5, None.into()).is_err());
+}
+
+#[test]
+fn should_accept_leaf_as_index_or_hash() {
+	let by_index: LeafRef = ::serde_json::from_str("3").unwrap();
+	assert!(match by_index { LeafRef::Index(3) => true, _ => false });
+
+	let hash = ::serde_json::to_string(&leaf("a")).unwrap();
+	let by_hash: LeafRef = ::serde_json::from_str(&hash).unwrap();
+	assert!(match by_hash { LeafRef::Hash(h) => h == leaf("a"), _ => false });
+}
+
+#[test]
+fn should_notify_subscribers_of_imported_and_finalized_inserts() {
+	let (merkle, _, _runtime) = setup();
+	// Inserts of blocks finalized before subscribing are not sent
+	merkle.index.finalize_block(block(2));
+
+	let (leaf_subscriber, leaf_id, mut leaf_transport) = Subscriber::new_test("test");
+	merkle.subscribe_leaves(Default::default(), leaf_subscriber);
+	let (root_subscriber, root_id, mut root_transport) = Subscriber::new_test("test");
+	merkle.subscribe_roots(Default::default(), root_subscriber);
+	assert_eq!(leaf_id.wait(), Ok(Ok(SubscriptionId::Number(0))));
+	assert_eq!(root_id.wait(), Ok(Ok(SubscriptionId::Number(1))));
+
+	merkle.index.import_leaves(block(3), block(2), 3, vec![leaf("f"), leaf("g")]).unwrap();
+	let imported = block_3_inserts(&merkle, &["f", "g"], false);
+	assert_eq!(notifications(&mut leaf_transport, 2), imported);
+	assert_eq!(notifications(&mut root_transport, 1), vec![imported[1].clone()]);
+
+	// Same inserts are sent again once their block is finalized
+	merkle.index.finalize_block(block(3));
+	let finalized = block_3_inserts(&merkle, &["f", "g"], true);
+	assert_eq!(notifications(&mut leaf_transport, 2), finalized);
+	assert_eq!(notifications(&mut root_transport, 1), vec![finalized[1].clone()]);
+}
+
+#[test]
+fn should_stop_notifying_unsubscribed_subscribers() {
+	let (merkle, _, _runtime) = setup();
+
+	let (leaf_subscriber, leaf_id, leaf_transport) = Subscriber::new_test("test");
+	merkle.subscribe_leaves(Default::default(), leaf_subscriber);
+	let (root_subscriber, root_id, root_transport) = Subscriber::new_test("test");
+	merkle.subscribe_roots(Default::default(), root_subscriber);
+	let leaf_id = leaf_id.wait().unwrap().unwrap();
+	let root_id = root_id.wait().unwrap().unwrap();
+
+	assert_eq!(merkle.unsubscribe_leaves(leaf_id.clone()), Ok(true));
+	assert_eq!(merkle.unsubscribe_leaves(leaf_id), Ok(false));
+	assert_eq!(merkle.unsubscribe_roots(root_id.clone()), Ok(true));
+	assert_eq!(merkle.unsubscribe_roots(root_id), Ok(false));
+
+	// Streams end once the subscriptions are dropped, later inserts only drop their index streams
+	assert_eq!(leaf_transport.collect().wait(), Ok(vec![]));
+	assert_eq!(root_transport.collect().wait(), Ok(vec![]));
+	merkle.index.import_leaves(block(3), block(2), 3, vec![leaf("f")]).unwrap();
+}
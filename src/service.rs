@@ -16,6 +16,8 @@ use consensus::{import_queue, start_aura, AuraImportQueue, SlotDuration, Nothing
 use client;
 use primitives::ed25519::Pair;
 use runtime_primitives::BasicInherentData as InherentData;
+use indexer::{self, MerkleIndex};
+use rpc;
 
 pub use substrate_executor::NativeExecutor;
 // Our native executor instance.
@@ -26,6 +28,13 @@ native_executor_instance!(
 	include_bytes!("../runtime/wasm/target/wasm32-unknown-unknown/release/substrate_merkle_tree_runtime.compact.wasm")
 );
 
+/// Node specific configuration.
+#[derive(Default)]
+pub struct NodeConfig {
+	/// Folder of the merkle index checkpoint. Index is only kept in memory when `None`.
+	pub merkle_index_dir: Option<String>,
+}
+
 construct_simple_protocol! {
 	/// Demo protocol attachment for substrate.
 	pub struct NodeProtocol where Block = Block { }
@@ -42,11 +51,18 @@ construct_service_factory! {
 		LightTransactionPoolApi = transaction_pool::ChainApi<client::Client<LightBackend<Self>, LightExecutor<Self>, Block, RuntimeApi>, Block>
 			{ |config, client| Ok(TransactionPool::new(config, transaction_pool::ChainApi::new(client))) },
 		Genesis = GenesisConfig,
-		Configuration = (),
+		Configuration = NodeConfig,
 		FullService = FullComponents<Self>
-			{ |config: FactoryFullConfiguration<Self>, executor: TaskExecutor|
-				FullComponents::<Factory>::new(config, executor)
-			},
+			{ |mut config: FactoryFullConfiguration<Self>, executor: TaskExecutor| {
+				let merkle_index = Arc::new(MerkleIndex::new(config.custom.merkle_index_dir.clone()));
+				// RPC servers are started here instead of by the service, with the merkle RPC added to them
+				let (rpc_http, rpc_ws) = (config.rpc_http.take(), config.rpc_ws.take());
+				let system = rpc::SystemInfo::new(&config);
+				let service = FullComponents::<Factory>::new(config, executor.clone())?;
+				executor.spawn(indexer::start(service.client(), merkle_index.clone(), service.on_exit()));
+				rpc::start(rpc_http, rpc_ws, system, &service, merkle_index, executor, service.on_exit())?;
+				Ok(service)
+			}},
 		AuthoritySetup = {
 			|service: Self::FullService, executor: TaskExecutor, key: Option<Arc<Pair>>| {
 				if let Some(key) = key {